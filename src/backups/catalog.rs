@@ -0,0 +1,708 @@
+use std::{fmt, sync::OnceLock};
+
+use regex::Regex;
+
+use crate::{
+  backups::{BackupKind, BackupObject, OplogPosition, gridfs},
+  datastores::{Datastore, DedupStore, dedup},
+  utils::{
+    config::{BackupCompression, BackupFormat},
+    logger::Logger,
+  },
+};
+
+static OBJECT_NAME_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// The name of a backup object:
+/// `backup_<name>_<timestamp>[.oplog|.changes|.sanitized].<format>[.gz][.dedup]`, incremental
+/// backups being marked with `.oplog`, change stream segments with `.changes`, sanitized variants
+/// with `.sanitized` and objects stored as an index of deduplicated chunks with `.dedup`. Objects
+/// may be stored under directories, like `cool/2026/10/backup_cool_1760000000.json`.
+#[derive(Debug, PartialEq)]
+pub struct ObjectName {
+  /// Directories of the object, ending with `/` unless empty.
+  pub directory: String,
+  pub backup_name: String,
+  pub timestamp: i64,
+  pub kind: BackupKind,
+  pub format: BackupFormat,
+  pub compression: BackupCompression,
+  pub dedup: bool,
+}
+
+impl ObjectName {
+  pub fn parse(object_name: &str) -> Option<Self> {
+    let object_name_regex = OBJECT_NAME_REGEX.get_or_init(|| {
+      Regex::new(
+        r"^backup_(\w+)_([0-9]+)(\.oplog|\.changes|\.sanitized)?\.(json|archive|bson)(\.gz)?(\.dedup)?$",
+      )
+      .expect("invalid regex")
+    });
+    let (directory, file_name) = match object_name.rfind('/') {
+      Some(end) => object_name.split_at(end + 1),
+      None => ("", object_name),
+    };
+    let captures = object_name_regex.captures(file_name)?;
+
+    Some(Self {
+      directory: directory.to_string(),
+      backup_name: captures[1].to_string(),
+      timestamp: captures[2].parse().ok()?,
+      kind: match captures.get(3).map(|kind| kind.as_str()) {
+        Some(".oplog") => BackupKind::Incremental,
+        Some(".changes") => BackupKind::Changes,
+        Some(_) => BackupKind::Sanitized,
+        None => BackupKind::Full,
+      },
+      format: match &captures[4] {
+        "archive" => BackupFormat::Archive,
+        "bson" => BackupFormat::Bson,
+        _ => BackupFormat::Json,
+      },
+      compression: match captures.get(5) {
+        Some(_) => BackupCompression::Gzip,
+        None => BackupCompression::None,
+      },
+      dedup: captures.get(6).is_some(),
+    })
+  }
+}
+
+impl fmt::Display for ObjectName {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let kind = match self.kind {
+      BackupKind::Full => "",
+      BackupKind::Incremental => ".oplog",
+      BackupKind::Changes => ".changes",
+      BackupKind::Sanitized => ".sanitized",
+    };
+    let extension = match self.format {
+      BackupFormat::Json => "json",
+      BackupFormat::Archive => "archive",
+      BackupFormat::Bson => "bson",
+    };
+    let compression = match self.compression {
+      BackupCompression::None => "",
+      BackupCompression::Gzip => ".gz",
+    };
+    let dedup = match self.dedup {
+      true => dedup::INDEX_EXTENSION,
+      false => "",
+    };
+
+    write!(
+      f,
+      "{}backup_{}_{}{}.{}{}{}",
+      self.directory, self.backup_name, self.timestamp, kind, extension, compression, dedup
+    )
+  }
+}
+
+pub struct BackupCatalog;
+
+impl BackupCatalog {
+  /// Lists the objects of a backup, oldest first.
+  pub fn list(datastore: &dyn Datastore, backup_name: &str) -> Result<Vec<String>, String> {
+    let mut objects: Vec<(i64, String)> = datastore
+      .list_objects()?
+      .into_iter()
+      .filter_map(|object| match ObjectName::parse(&object) {
+        Some(name) if name.backup_name == backup_name => Some((name.timestamp, object)),
+        _ => None,
+      })
+      .collect();
+    objects.sort();
+
+    Ok(objects.into_iter().map(|(_, object)| object).collect())
+  }
+
+  /// Returns the latest full backup object, leaving out incremental backups.
+  pub fn latest(datastore: &dyn Datastore, backup_name: &str) -> Result<String, String> {
+    Self::latest_of(datastore, backup_name, BackupKind::Full)
+  }
+
+  /// Returns the latest backup object of the given kind.
+  pub fn latest_of(
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    kind: BackupKind,
+  ) -> Result<String, String> {
+    Self::list(datastore, backup_name)?
+      .into_iter()
+      .rfind(|object| ObjectName::parse(object).is_some_and(|name| name.kind == kind))
+      .ok_or(format!("No backup found for {backup_name}"))
+  }
+
+  pub fn load(
+    datastore: &dyn Datastore,
+    object_name: &str,
+    encryption_key: Option<&str>,
+  ) -> Result<BackupObject, String> {
+    let name =
+      ObjectName::parse(object_name).ok_or(format!("Invalid backup object name {object_name}"))?;
+    // Deduplicated chunks are compressed and encrypted one by one
+    let mut object = match name.dedup {
+      true => BackupObject::decode(
+        &DedupStore::new(datastore, name.compression, encryption_key).get(object_name)?,
+        name.format,
+        BackupCompression::None,
+        None,
+      )?,
+      false => BackupObject::decode(
+        &datastore.get_object(object_name.to_string())?,
+        name.format,
+        name.compression,
+        encryption_key,
+      )?,
+    };
+    gridfs::load_files(&mut object, datastore, encryption_key)?;
+    Ok(object)
+  }
+
+  /// Deletes the objects of a backup older than its `keep` latest full backups, returning them.
+  /// The chunks and GridFS objects they leave unreferenced are deleted by garbage collection.
+  pub fn prune(
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    keep: usize,
+  ) -> Result<Vec<String>, String> {
+    if keep == 0 {
+      return Err("Pruning must keep at least one backup".to_string());
+    }
+    let objects: Vec<ObjectName> = Self::list(datastore, backup_name)?
+      .iter()
+      .filter_map(|object| ObjectName::parse(object))
+      .collect();
+
+    // Incremental backups, segments and variants go along with the full backup they follow
+    let fulls: Vec<i64> = objects
+      .iter()
+      .filter(|name| name.kind == BackupKind::Full)
+      .map(|name| name.timestamp)
+      .collect();
+    let Some(oldest_kept) = fulls.len().checked_sub(keep).map(|i| fulls[i]) else {
+      return Ok(Vec::new());
+    };
+
+    // Change segments stored while the oldest kept full backup was dumped hold events following
+    // it, only those stored before the previous full backup are needed by no kept one
+    let previous = fulls.len().checked_sub(keep + 1).map(|i| fulls[i]);
+    let is_pruned = |name: &&ObjectName| match name.kind {
+      BackupKind::Changes => previous.is_some_and(|previous| name.timestamp < previous),
+      _ => name.timestamp < oldest_kept,
+    };
+
+    let mut deleted = Vec::new();
+    for name in objects.iter().filter(is_pruned) {
+      let object_name = name.to_string();
+      datastore.delete_object(&object_name)?;
+      let references = gridfs::references_name(&object_name);
+      if datastore.object_exists(&references) {
+        datastore.delete_object(&references)?;
+      }
+      deleted.push(object_name);
+    }
+
+    if !deleted.is_empty() {
+      Logger::info(&format!(
+        "Pruned {} objects of {backup_name}",
+        deleted.len()
+      ));
+    }
+    Ok(deleted)
+  }
+
+  /// Deletes the deduplicated chunks and the GridFS objects no backup refers to anymore, returning
+  /// their numbers. Fails while backups are being stored or another collection runs.
+  pub fn collect_garbage(datastore: &dyn Datastore) -> Result<(usize, usize), String> {
+    let chunks = DedupStore::collect_garbage(datastore)?;
+    let files = gridfs::collect_garbage(datastore)?;
+    Ok((chunks, files))
+  }
+
+  /// Loads the full backup to restore to the `until` Unix timestamp, the latest one created
+  /// before it unless `base` is given, and the incremental backups chained to it, then the change
+  /// segments following them, oldest first.
+  pub fn load_chain(
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    base: Option<&str>,
+    until: i64,
+    encryption_key: Option<&str>,
+  ) -> Result<(BackupObject, Vec<BackupObject>), String> {
+    let objects: Vec<ObjectName> = Self::list(datastore, backup_name)?
+      .iter()
+      .filter_map(|object| ObjectName::parse(object))
+      .collect();
+
+    let base_name = match base {
+      Some(base) => base.to_string(),
+      None => objects
+        .iter()
+        .rfind(|name| name.kind == BackupKind::Full && name.timestamp <= until)
+        .ok_or(format!(
+          "No full backup of {backup_name} found before {until}"
+        ))?
+        .to_string(),
+    };
+    let base_timestamp = ObjectName::parse(&base_name)
+      .ok_or(format!("Invalid backup object name {base_name}"))?
+      .timestamp;
+    let base = Self::load(datastore, &base_name, encryption_key)?;
+    // Backups without oplog access replay change segments from their snapshot
+    let mut position = base
+      .manifest
+      .oplog_end
+      .or(base.manifest.cluster_time)
+      .ok_or(format!(
+        "Backup {base_name} has no oplog position to replay from"
+      ))?;
+
+    let mut segments = Vec::new();
+    for name in objects
+      .iter()
+      .filter(|name| name.kind == BackupKind::Incremental && name.timestamp >= base_timestamp)
+    {
+      if position.time as i64 >= until {
+        break;
+      }
+
+      let object_name = name.to_string();
+      let segment = Self::load(datastore, &object_name, encryption_key)?;
+      if segment.manifest.base.as_deref() != Some(base_name.as_str()) {
+        continue;
+      }
+      if segment.manifest.oplog_start != Some(position) {
+        return Err(format!(
+          "Incremental backup {object_name} does not follow the previous one, the oplog chain is broken"
+        ));
+      }
+
+      position = segment.manifest.oplog_end.unwrap_or(position);
+      segments.push(segment);
+    }
+
+    // Segments stored after the previous full backup may hold events following the base, which
+    // was dumped before being stored
+    let previous = objects
+      .iter()
+      .filter(|name| name.kind == BackupKind::Full && name.timestamp < base_timestamp)
+      .map(|name| name.timestamp)
+      .next_back()
+      .unwrap_or(i64::MIN);
+    for name in objects
+      .iter()
+      .filter(|name| name.kind == BackupKind::Changes && name.timestamp >= previous)
+    {
+      if position.time as i64 >= until {
+        break;
+      }
+
+      let mut segment = Self::load(datastore, &name.to_string(), encryption_key)?;
+      if segment
+        .manifest
+        .oplog_start
+        .is_some_and(|start| start.time as i64 >= until)
+      {
+        break;
+      }
+      for dump in &mut segment.collections {
+        dump.documents.retain(|event| {
+          event
+            .get_timestamp("clusterTime")
+            .is_ok_and(|time| OplogPosition::from(time) > position)
+        });
+      }
+      if let Some(end) = segment.manifest.oplog_end.filter(|end| *end > position) {
+        position = end;
+        segments.push(segment);
+      }
+    }
+
+    if (position.time as i64) < until {
+      Logger::warn(&format!(
+        "Incremental backups and change segments of {backup_name} only go up to {}, restoring to \
+         that point",
+        position.time
+      ));
+    }
+
+    Ok((base, segments))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{Timestamp, rawdoc};
+
+  use crate::{
+    backups::{
+      BackupCatalog, BackupKind, BackupManifest, BackupObject, CollectionDump, CollectionManifest,
+      ObjectName, OplogPosition,
+    },
+    datastores::{Datastore, DedupStore, FilesystemDatastore},
+    tests::{clean_test_dir, get_test_dir_path},
+    utils::{
+      config::{BackupCompression, BackupFormat, JsonMode},
+      crypto::generate_key,
+    },
+  };
+
+  fn put_object(
+    datastore: &FilesystemDatastore,
+    object_name: &str,
+    base: Option<&str>,
+    oplog: (Option<u32>, u32),
+  ) {
+    let position = |time| OplogPosition { time, increment: 1 };
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    if base.is_some() {
+      manifest.kind = BackupKind::Incremental;
+    }
+    manifest.base = base.map(str::to_string);
+    manifest.oplog_start = oplog.0.map(position);
+    manifest.oplog_end = Some(position(oplog.1));
+
+    let object = BackupObject {
+      manifest,
+      collections: Vec::new(),
+    };
+    let content = object
+      .encode(
+        BackupFormat::Json,
+        JsonMode::Canonical,
+        BackupCompression::None,
+        None,
+      )
+      .unwrap();
+    datastore.put_object(object_name, &content).unwrap();
+  }
+
+  fn put_changes(datastore: &FilesystemDatastore, object_name: &str, times: &[u32]) {
+    let position = |time| OplogPosition { time, increment: 1 };
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest.kind = BackupKind::Changes;
+    manifest.oplog_start = times.first().copied().map(position);
+    manifest.oplog_end = times.last().copied().map(position);
+    manifest.collections.push(CollectionManifest {
+      database: "app".to_string(),
+      name: "$changes".to_string(),
+      document_count: times.len() as u64,
+      ..Default::default()
+    });
+
+    let object = BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: "app".to_string(),
+        name: "$changes".to_string(),
+        documents: times
+          .iter()
+          .map(|time| rawdoc! { "clusterTime": Timestamp { time: *time, increment: 1 } })
+          .collect(),
+      }],
+    };
+    let content = object
+      .encode(
+        BackupFormat::Json,
+        JsonMode::Canonical,
+        BackupCompression::None,
+        None,
+      )
+      .unwrap();
+    datastore.put_object(object_name, &content).unwrap();
+  }
+
+  #[test]
+  fn catalog_parse_object_name() {
+    let name = ObjectName::parse("backup_my_cool_1760000000.json").unwrap();
+    assert_eq!(name.backup_name, "my_cool");
+    assert_eq!(name.timestamp, 1760000000);
+    assert_eq!(name.format, BackupFormat::Json);
+    assert_eq!(name.compression, BackupCompression::None);
+
+    let name = ObjectName::parse("backup_cool_1760000000.archive.gz").unwrap();
+    assert_eq!(name.format, BackupFormat::Archive);
+    assert_eq!(name.compression, BackupCompression::Gzip);
+    assert_eq!(name.to_string(), "backup_cool_1760000000.archive.gz");
+
+    assert_eq!(ObjectName::parse("backup_cool.json"), None);
+    let name = ObjectName::parse("backup_cool_1760000000.bson").unwrap();
+    assert_eq!(name.format, BackupFormat::Bson);
+    assert_eq!(name.compression, BackupCompression::None);
+
+    assert_eq!(ObjectName::parse("backup_cool_1760000000.xml"), None);
+
+    let name = ObjectName::parse("backup_my_cool_1760000000.oplog.bson.gz").unwrap();
+    assert_eq!(name.backup_name, "my_cool");
+    assert_eq!(name.kind, BackupKind::Incremental);
+    assert_eq!(name.format, BackupFormat::Bson);
+    assert_eq!(name.to_string(), "backup_my_cool_1760000000.oplog.bson.gz");
+    assert_eq!(
+      ObjectName::parse("backup_cool_1760000000.json")
+        .unwrap()
+        .kind,
+      BackupKind::Full
+    );
+
+    let name = ObjectName::parse("backup_cool_1760000000.sanitized.json.gz").unwrap();
+    assert_eq!(name.kind, BackupKind::Sanitized);
+    assert_eq!(name.to_string(), "backup_cool_1760000000.sanitized.json.gz");
+    assert!(!name.dedup);
+
+    let name = ObjectName::parse("backup_cool_1760000000.bson.gz.dedup").unwrap();
+    assert!(name.dedup);
+    assert_eq!(name.compression, BackupCompression::Gzip);
+    assert_eq!(name.to_string(), "backup_cool_1760000000.bson.gz.dedup");
+
+    let name = ObjectName::parse("cool/2026/10/backup_cool_1760000000.json").unwrap();
+    assert_eq!(name.directory, "cool/2026/10/");
+    assert_eq!(name.backup_name, "cool");
+    assert_eq!(name.to_string(), "cool/2026/10/backup_cool_1760000000.json");
+    assert_eq!(ObjectName::parse("backup_cool_1760000000.json/data"), None);
+  }
+
+  #[test]
+  fn catalog_latest() {
+    let test_dir_path = get_test_dir_path("catalog_latest");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    for object in [
+      "backup_cool_20.json",
+      "backup_cool_100.archive.gz",
+      "backup_cool_3.json",
+      "backup_cool_200.oplog.json",
+      "backup_cool_150.sanitized.json",
+      "backup_awesome_1000.json",
+    ] {
+      let _ = datastore.put_object(object, b"{}");
+    }
+
+    assert_eq!(
+      BackupCatalog::list(&datastore, "cool").unwrap(),
+      vec![
+        "backup_cool_3.json",
+        "backup_cool_20.json",
+        "backup_cool_100.archive.gz",
+        "backup_cool_150.sanitized.json",
+        "backup_cool_200.oplog.json"
+      ]
+    );
+    assert_eq!(
+      BackupCatalog::latest(&datastore, "cool").unwrap(),
+      "backup_cool_100.archive.gz"
+    );
+    assert_eq!(
+      BackupCatalog::latest_of(&datastore, "cool", BackupKind::Sanitized).unwrap(),
+      "backup_cool_150.sanitized.json"
+    );
+    assert!(BackupCatalog::latest(&datastore, "unknown").is_err());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn catalog_load_dedup() {
+    let test_dir_path = get_test_dir_path("catalog_load_dedup");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+    let key = generate_key();
+
+    let object = BackupObject {
+      manifest: BackupManifest::new("cool", "Cool Backup"),
+      collections: Vec::new(),
+    };
+    let content = object
+      .encode(
+        BackupFormat::Bson,
+        JsonMode::default(),
+        BackupCompression::None,
+        None,
+      )
+      .unwrap();
+    DedupStore::new(&datastore, BackupCompression::Gzip, Some(&key))
+      .put("backup_cool_100.bson.gz.dedup", &content)
+      .unwrap();
+
+    let loaded = BackupCatalog::load(&datastore, "backup_cool_100.bson.gz.dedup", Some(&key));
+    assert_eq!(loaded.unwrap().manifest.backup_name, "cool");
+    assert!(BackupCatalog::load(&datastore, "backup_cool_100.bson.gz.dedup", None).is_err());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn catalog_prune() {
+    let test_dir_path = get_test_dir_path("catalog_prune");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    for object in [
+      "backup_cool_90.changes.json",
+      "backup_cool_100.json",
+      "backup_cool_101.sanitized.json",
+      "backup_cool_120.changes.json",
+      "backup_cool_150.oplog.json",
+      "backup_cool_200.json",
+      "backup_cool_250.oplog.json",
+      "backup_cool_300.json",
+      "backup_awesome_50.json",
+    ] {
+      let _ = datastore.put_object(object, b"{}");
+    }
+    let _ = datastore.put_object("backup_cool_100.json.gridfs", b"[]");
+
+    assert!(BackupCatalog::prune(&datastore, "cool", 0).is_err());
+    assert!(
+      BackupCatalog::prune(&datastore, "cool", 3)
+        .unwrap()
+        .is_empty()
+    );
+    assert_eq!(
+      BackupCatalog::prune(&datastore, "cool", 2).unwrap(),
+      vec![
+        "backup_cool_90.changes.json",
+        "backup_cool_100.json",
+        "backup_cool_101.sanitized.json",
+        "backup_cool_150.oplog.json"
+      ]
+    );
+    // Change segments stored since the previous full backup may follow the oldest kept one
+    assert_eq!(
+      BackupCatalog::list(&datastore, "cool").unwrap(),
+      vec![
+        "backup_cool_120.changes.json",
+        "backup_cool_200.json",
+        "backup_cool_250.oplog.json",
+        "backup_cool_300.json"
+      ]
+    );
+    assert!(datastore.object_exists("backup_awesome_50.json"));
+    assert!(!datastore.object_exists("backup_cool_100.json.gridfs"));
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn catalog_nested_keys() {
+    let test_dir_path = get_test_dir_path("catalog_nested_keys");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    put_object(
+      &datastore,
+      "cool/2026/09/backup_cool_100.json",
+      None,
+      (None, 90),
+    );
+    put_object(
+      &datastore,
+      "cool/2026/10/backup_cool_200.json",
+      None,
+      (None, 190),
+    );
+    put_object(&datastore, "backup_cool_300.json", None, (None, 290));
+
+    assert_eq!(
+      BackupCatalog::list(&datastore, "cool").unwrap(),
+      vec![
+        "cool/2026/09/backup_cool_100.json",
+        "cool/2026/10/backup_cool_200.json",
+        "backup_cool_300.json"
+      ]
+    );
+    let chain = BackupCatalog::load_chain(&datastore, "cool", None, 250, None).unwrap();
+    assert_eq!(chain.0.manifest.oplog_end.unwrap().time, 190);
+
+    assert_eq!(
+      BackupCatalog::prune(&datastore, "cool", 2).unwrap(),
+      vec!["cool/2026/09/backup_cool_100.json"]
+    );
+    assert_eq!(
+      BackupCatalog::latest(&datastore, "cool").unwrap(),
+      "backup_cool_300.json"
+    );
+    assert!(datastore.object_exists("cool/2026/10/backup_cool_200.json"));
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn catalog_load_chain() {
+    let test_dir_path = get_test_dir_path("catalog_load_chain");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    let base = Some("backup_cool_100.json");
+    put_object(&datastore, "backup_cool_100.json", None, (None, 90));
+    put_object(
+      &datastore,
+      "backup_cool_200.oplog.json",
+      base,
+      (Some(90), 190),
+    );
+    put_object(
+      &datastore,
+      "backup_cool_300.oplog.json",
+      base,
+      (Some(190), 290),
+    );
+    put_object(&datastore, "backup_cool_400.json", None, (None, 390));
+    put_object(
+      &datastore,
+      "backup_cool_500.oplog.json",
+      base,
+      (Some(290), 490),
+    );
+
+    let chain = |base, until| {
+      BackupCatalog::load_chain(&datastore, "cool", base, until, None)
+        .map(|(base, segments)| (base.manifest.oplog_end.unwrap().time, segments.len()))
+    };
+    assert_eq!(chain(None, 250), Ok((90, 2)));
+    assert_eq!(chain(None, 150), Ok((90, 1)));
+    assert_eq!(chain(None, 450), Ok((390, 0)));
+    assert_eq!(chain(base, 1000), Ok((90, 3)));
+    assert!(chain(None, 50).is_err());
+
+    put_object(
+      &datastore,
+      "backup_cool_600.oplog.json",
+      base,
+      (Some(500), 590),
+    );
+    assert!(chain(base, 1000).is_err());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn catalog_load_changes() {
+    let test_dir_path = get_test_dir_path("catalog_load_changes");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    put_changes(&datastore, "backup_cool_95.changes.json", &[80, 92]);
+    put_object(&datastore, "backup_cool_100.json", None, (None, 90));
+    put_changes(&datastore, "backup_cool_200.changes.json", &[150, 190]);
+    put_object(&datastore, "backup_cool_400.json", None, (None, 390));
+    put_changes(&datastore, "backup_cool_420.changes.json", &[380, 395]);
+
+    let chain = |until| {
+      BackupCatalog::load_chain(&datastore, "cool", None, until, None).map(|(_, segments)| {
+        segments
+          .iter()
+          .map(|segment| segment.collections[0].documents.len())
+          .collect::<Vec<_>>()
+      })
+    };
+    // Segments stored while the base was dumped hold the events following it
+    assert_eq!(chain(300), Ok(vec![1, 2]));
+    assert_eq!(chain(120), Ok(vec![1]));
+    // Only the events following the latest base are replayed
+    assert_eq!(chain(450), Ok(vec![1]));
+
+    clean_test_dir(test_dir_path);
+  }
+}
@@ -0,0 +1,578 @@
+use std::collections::HashMap;
+
+use bson::{Bson, RawDocumentBuf};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+  backups::{
+    BackupManifest, BackupObject, CollectionDump, CollectionManifest, CollectionSource,
+    OplogPosition,
+    parallel::{self, ReadBatch, ReadTask},
+  },
+  datastores::Datastore,
+  utils::config::{BackupCompression, BackupFormat, JsonMode},
+};
+
+/// Progress of a dump, stored next to the backup objects while it runs, so that a rerun of an
+/// interrupted backup resumes it instead of starting over.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+  /// Identifies the collections and queries of the dump, a rerun reading other ones starts over.
+  pub plan: String,
+  /// Unix timestamp of the first run, naming its part objects.
+  pub started: i64,
+  /// Oplog position read before the first run, from which the oplog covers every part.
+  pub oplog_position: Option<OplogPosition>,
+  pub parts: Vec<CheckpointPart>,
+}
+
+/// A read of the dump, stored in part objects batch by batch as it goes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointPart {
+  /// Index of the collection in the manifest.
+  pub collection: usize,
+  pub read: ReadTask,
+  /// Number of documents of the part, once entirely read.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub documents: Option<u64>,
+  /// Number of documents of every part object written so far.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub batches: Vec<u64>,
+  /// `_id` of the last document written so far, after which a sorted read resumes.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub last_id: Option<Bson>,
+}
+
+impl Checkpoint {
+  pub fn new(
+    plan: String,
+    oplog_position: Option<OplogPosition>,
+    parts: Vec<CheckpointPart>,
+  ) -> Self {
+    Self {
+      plan,
+      started: chrono::Local::now().timestamp(),
+      oplog_position,
+      parts,
+    }
+  }
+
+  pub fn object_name(backup_name: &str) -> String {
+    format!("backup_{backup_name}.checkpoint.json")
+  }
+
+  pub fn part_name(&self, backup_name: &str, part: usize, batch: usize) -> String {
+    format!(
+      "backup_{backup_name}.checkpoint_{}_{part}_{batch}.part",
+      self.started
+    )
+  }
+
+  /// Fingerprint of the collections and queries of a dump.
+  pub fn plan(manifest: &BackupManifest) -> String {
+    let collections: Vec<_> = manifest
+      .collections
+      .iter()
+      .map(|collection| {
+        (
+          collection.namespace(),
+          &collection.filter,
+          &collection.projection,
+        )
+      })
+      .collect();
+    let plan = serde_json::to_vec(&(&manifest.databases, collections)).unwrap_or_default();
+
+    Sha256::digest(plan)
+      .iter()
+      .map(|byte| format!("{byte:02x}"))
+      .collect()
+  }
+
+  pub fn load(datastore: &dyn Datastore, backup_name: &str) -> Result<Option<Self>, String> {
+    let object_name = Self::object_name(backup_name);
+    if !datastore.object_exists(&object_name) {
+      return Ok(None);
+    }
+
+    let content = datastore.get_object(object_name)?;
+    serde_json::from_slice(&content)
+      .map(Some)
+      .map_err(|err| format!("Invalid checkpoint: {}", err))
+  }
+
+  pub fn save(&self, datastore: &dyn Datastore, backup_name: &str) -> Result<(), String> {
+    let object_name = Self::object_name(backup_name);
+    let content =
+      serde_json::to_vec(self).map_err(|err| format!("Cannot serialize checkpoint: {}", err))?;
+
+    datastore.replace_object(&object_name, &content)
+  }
+
+  /// Writes a batch of documents of the part `batch.task`, recording the part as done with its
+  /// last batch.
+  pub fn store_batch(
+    &mut self,
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    batch: &ReadBatch,
+    compression: BackupCompression,
+    encryption_key: Option<&str>,
+  ) -> Result<(), String> {
+    let (part, documents) = (batch.task, &batch.documents);
+    if !documents.is_empty() {
+      let read = &self.parts[part].read;
+      let mut manifest = BackupManifest::new(backup_name, "");
+      manifest.collections.push(CollectionManifest {
+        database: read.database.clone(),
+        name: read.collection.clone(),
+        document_count: documents.len() as u64,
+        ..Default::default()
+      });
+      let object = BackupObject {
+        manifest,
+        collections: vec![CollectionDump {
+          database: read.database.clone(),
+          name: read.collection.clone(),
+          documents: documents.to_vec(),
+        }],
+      };
+
+      // Parts keep the exact bytes of the documents, whatever the format of the backup
+      let content = object.encode(
+        BackupFormat::Bson,
+        JsonMode::default(),
+        compression,
+        encryption_key,
+      )?;
+      let object_name = self.part_name(backup_name, part, self.parts[part].batches.len());
+      datastore.replace_object(&object_name, &content)?;
+      self.parts[part].batches.push(documents.len() as u64);
+      self.parts[part].last_id = documents
+        .last()
+        .and_then(|document| document.get("_id").ok().flatten())
+        .and_then(|id| Bson::try_from(id).ok());
+    }
+
+    if batch.last {
+      self.parts[part].documents = Some(self.parts[part].batches.iter().sum());
+    }
+    self.save(datastore, backup_name)
+  }
+
+  /// Number of documents stored by the parts of the collection at `index` in the manifest.
+  pub fn collection_documents(&self, index: usize) -> u64 {
+    self
+      .parts
+      .iter()
+      .filter(|part| part.collection == index)
+      .flat_map(|part| &part.batches)
+      .sum()
+  }
+
+  /// Reads the documents of every batch of a part.
+  pub fn load_part(
+    &self,
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    part: usize,
+    compression: BackupCompression,
+    encryption_key: Option<&str>,
+  ) -> Result<Vec<RawDocumentBuf>, String> {
+    let mut documents = Vec::new();
+    for batch in 0..self.parts[part].batches.len() {
+      documents.extend(self.load_batch(
+        datastore,
+        backup_name,
+        part,
+        batch,
+        compression,
+        encryption_key,
+      )?);
+    }
+    Ok(documents)
+  }
+
+  /// Reads the documents of a batch of a part.
+  pub fn load_batch(
+    &self,
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    part: usize,
+    batch: usize,
+    compression: BackupCompression,
+    encryption_key: Option<&str>,
+  ) -> Result<Vec<RawDocumentBuf>, String> {
+    let object_name = self.part_name(backup_name, part, batch);
+    let content = datastore.get_object(object_name.clone())?;
+    let object = BackupObject::decode(&content, BackupFormat::Bson, compression, encryption_key)
+      .map_err(|err| format!("Cannot read {}: {}", object_name, err))?;
+
+    let documents: Vec<RawDocumentBuf> = object
+      .collections
+      .into_iter()
+      .flat_map(|dump| dump.documents)
+      .collect();
+    if documents.len() as u64 != self.parts[part].batches[batch] {
+      return Err(format!("{object_name} is incomplete"));
+    }
+    Ok(documents)
+  }
+
+  /// The read of a part from where it stopped, after the last document written when it is
+  /// sorted.
+  pub fn next_read(&self, part: usize) -> ReadTask {
+    let part = &self.parts[part];
+    let mut read = part.read.clone();
+    if let Some(last_id) = &part.last_id {
+      read.filter = parallel::resume_filter(&read.filter, last_id);
+    }
+    read
+  }
+
+  /// Checks that the recorded objects of the parts are all there, to resume from them.
+  pub fn check_parts(&self, datastore: &dyn Datastore, backup_name: &str) -> Result<(), String> {
+    for (part, checkpoint_part) in self.parts.iter().enumerate() {
+      for batch in 0..checkpoint_part.batches.len() {
+        let object_name = self.part_name(backup_name, part, batch);
+        if !datastore.object_exists(&object_name) {
+          return Err(format!("{object_name} is missing"));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Prepares a part read partly to go on after its last recorded object, deleting the one
+  /// written after it, if any. Parts whose read is not sorted, or whose documents have no `_id`,
+  /// lose their objects to be read again from the start.
+  pub fn resume_part(
+    &mut self,
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    part: usize,
+  ) -> Result<(), String> {
+    let checkpoint_part = &self.parts[part];
+    if checkpoint_part.read.sort.is_none() || checkpoint_part.last_id.is_none() {
+      self.delete_batches(datastore, backup_name, part)?;
+      self.parts[part].batches.clear();
+      self.parts[part].last_id = None;
+      return Ok(());
+    }
+
+    let object_name = self.part_name(backup_name, part, checkpoint_part.batches.len());
+    if datastore.object_exists(&object_name) {
+      datastore.delete_object(&object_name)?;
+    }
+    Ok(())
+  }
+
+  /// Deletes the checkpoint and every part object it may have written, including those written
+  /// before the checkpoint recorded them.
+  pub fn clean(&self, datastore: &dyn Datastore, backup_name: &str) -> Result<(), String> {
+    for part in 0..self.parts.len() {
+      self.delete_batches(datastore, backup_name, part)?;
+    }
+
+    let object_name = Self::object_name(backup_name);
+    if datastore.object_exists(&object_name) {
+      datastore.delete_object(&object_name)?;
+    }
+    Ok(())
+  }
+
+  /// Deletes the recorded objects of a part, and the one written after them, if any.
+  fn delete_batches(
+    &self,
+    datastore: &dyn Datastore,
+    backup_name: &str,
+    part: usize,
+  ) -> Result<(), String> {
+    for batch in 0..=self.parts[part].batches.len() {
+      let object_name = self.part_name(backup_name, part, batch);
+      if datastore.object_exists(&object_name) {
+        datastore.delete_object(&object_name)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Reads the documents of a dump back from the parts of its checkpoint, batch by batch, but for
+/// the collections already loaded in memory.
+pub struct PartsSource<'a> {
+  pub checkpoint: &'a Checkpoint,
+  pub datastore: &'a dyn Datastore,
+  pub backup_name: &'a str,
+  pub compression: BackupCompression,
+  pub encryption_key: Option<&'a str>,
+  /// Collections loaded in memory, by index in the manifest.
+  pub loaded: HashMap<usize, &'a CollectionDump>,
+}
+
+impl CollectionSource for PartsSource<'_> {
+  fn count(&self, index: usize) -> u64 {
+    match self.loaded.get(&index) {
+      Some(dump) => dump.documents.len() as u64,
+      None => self.checkpoint.collection_documents(index),
+    }
+  }
+
+  fn read_collection(
+    &self,
+    index: usize,
+    on_batch: &mut dyn FnMut(&[RawDocumentBuf]) -> Result<(), String>,
+  ) -> Result<(), String> {
+    if let Some(dump) = self.loaded.get(&index) {
+      return on_batch(&dump.documents);
+    }
+
+    for (part, checkpoint_part) in self.checkpoint.parts.iter().enumerate() {
+      if checkpoint_part.collection != index {
+        continue;
+      }
+      for batch in 0..checkpoint_part.batches.len() {
+        on_batch(&self.checkpoint.load_batch(
+          self.datastore,
+          self.backup_name,
+          part,
+          batch,
+          self.compression,
+          self.encryption_key,
+        )?)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use bson::{Bson, doc, rawdoc};
+
+  use crate::{
+    backups::{
+      BackupManifest, BackupObject, CollectionDump, CollectionManifest,
+      checkpoint::{Checkpoint, CheckpointPart, PartsSource},
+      object,
+      parallel::{ReadBatch, ReadTask},
+    },
+    datastores::{Datastore, FilesystemDatastore},
+    tests::{clean_test_dir, get_test_dir_path},
+    utils::{
+      config::{BackupCompression, BackupFormat, JsonMode},
+      crypto::generate_key,
+    },
+  };
+
+  fn part(collection: &str) -> CheckpointPart {
+    CheckpointPart {
+      collection: 0,
+      read: ReadTask {
+        database: "app".to_string(),
+        collection: collection.to_string(),
+        filter: doc! { "_id": { "$lt": 10 } },
+        projection: None,
+        sort: Some(doc! { "_id": 1 }),
+      },
+      documents: None,
+      batches: Vec::new(),
+      last_id: None,
+    }
+  }
+
+  #[test]
+  fn checkpoint_plan() {
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest.databases = vec!["app".to_string()];
+    manifest.collections.push(CollectionManifest {
+      database: "app".to_string(),
+      name: "users".to_string(),
+      ..Default::default()
+    });
+    let plan = Checkpoint::plan(&manifest);
+
+    // Counts and indexes change between runs without changing what is read
+    manifest.collections[0].document_count = 42;
+    assert_eq!(Checkpoint::plan(&manifest), plan);
+
+    manifest.collections[0].filter = Some(doc! { "active": true });
+    assert_ne!(Checkpoint::plan(&manifest), plan);
+  }
+
+  #[test]
+  fn checkpoint_parts() {
+    let test_dir_path = get_test_dir_path("checkpoint_parts");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+    let key = generate_key();
+
+    assert_eq!(Checkpoint::load(&datastore, "cool").unwrap(), None);
+
+    let mut checkpoint =
+      Checkpoint::new("plan".to_string(), None, vec![part("users"), part("logs")]);
+    checkpoint.save(&datastore, "cool").unwrap();
+
+    let batch = |task, documents: &[i32], last| ReadBatch {
+      task,
+      documents: documents.iter().map(|id| rawdoc! { "_id": *id }).collect(),
+      last,
+    };
+    let mut store = |batch| {
+      checkpoint.store_batch(
+        &datastore,
+        "cool",
+        &batch,
+        BackupCompression::Gzip,
+        Some(&key),
+      )
+    };
+    store(batch(0, &[1, 2], false)).unwrap();
+    store(batch(1, &[10], false)).unwrap();
+    store(batch(0, &[3], false)).unwrap();
+    store(batch(0, &[], true)).unwrap();
+
+    let mut loaded = Checkpoint::load(&datastore, "cool").unwrap().unwrap();
+    assert_eq!(loaded, checkpoint);
+    assert_eq!(loaded.parts[0].documents, Some(3));
+    assert_eq!(loaded.parts[0].batches, vec![2, 1]);
+    assert_eq!(loaded.parts[1].documents, None);
+    assert_eq!(
+      loaded
+        .load_part(&datastore, "cool", 0, BackupCompression::Gzip, Some(&key))
+        .unwrap(),
+      vec![
+        rawdoc! { "_id": 1 },
+        rawdoc! { "_id": 2 },
+        rawdoc! { "_id": 3 }
+      ]
+    );
+    assert!(
+      loaded
+        .load_part(&datastore, "cool", 0, BackupCompression::Gzip, None)
+        .is_err()
+    );
+    loaded.check_parts(&datastore, "cool").unwrap();
+
+    // Parts read partly go on after their last stored document, dropping the object written
+    // after it
+    assert_eq!(loaded.parts[1].last_id, Some(Bson::Int32(10)));
+    datastore
+      .put_object(&loaded.part_name("cool", 1, 1), b"unrecorded")
+      .unwrap();
+    loaded.resume_part(&datastore, "cool", 1).unwrap();
+    assert_eq!(loaded.parts[1].batches, vec![1]);
+    assert!(!datastore.object_exists(&loaded.part_name("cool", 1, 1)));
+    assert_eq!(
+      loaded.next_read(1).filter,
+      doc! { "$and": [
+        { "_id": { "$lt": 10 } },
+        { "$expr": { "$gt": ["$_id", { "$literal": 10 }] } },
+      ] }
+    );
+
+    // Unsorted parts are read again from the start
+    loaded.parts[1].read.sort = None;
+    loaded.resume_part(&datastore, "cool", 1).unwrap();
+    assert!(loaded.parts[1].batches.is_empty());
+    assert_eq!(loaded.next_read(1), loaded.parts[1].read);
+    assert!(!datastore.object_exists(&loaded.part_name("cool", 1, 0)));
+
+    datastore
+      .delete_object(&loaded.part_name("cool", 0, 1))
+      .unwrap();
+    assert!(loaded.check_parts(&datastore, "cool").is_err());
+
+    loaded.clean(&datastore, "cool").unwrap();
+    assert_eq!(Checkpoint::load(&datastore, "cool").unwrap(), None);
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn checkpoint_parts_source() {
+    let test_dir_path = get_test_dir_path("checkpoint_parts_source");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    let mut collections = Vec::new();
+    for name in ["users", "fs.files"] {
+      manifest.collections.push(CollectionManifest {
+        database: "app".to_string(),
+        name: name.to_string(),
+        ..Default::default()
+      });
+      collections.push(CollectionDump {
+        database: "app".to_string(),
+        name: name.to_string(),
+        documents: Vec::new(),
+      });
+    }
+
+    // Two ranges of users, read in batches, then the files loaded in memory
+    let mut checkpoint = Checkpoint::new("plan".to_string(), None, vec![part("users"); 2]);
+    for (task, documents) in [(0, 1..3), (1, 3..4), (0, 4..6), (1, 6..6)] {
+      let batch = ReadBatch {
+        task,
+        documents: documents.map(|id| rawdoc! { "_id": id }).collect(),
+        last: true,
+      };
+      checkpoint
+        .store_batch(&datastore, "cool", &batch, BackupCompression::Gzip, None)
+        .unwrap();
+    }
+    collections[1].documents.push(rawdoc! { "_id": "file" });
+
+    let source = PartsSource {
+      checkpoint: &checkpoint,
+      datastore: &datastore,
+      backup_name: "cool",
+      compression: BackupCompression::Gzip,
+      encryption_key: None,
+      loaded: HashMap::from([(1, &collections[1])]),
+    };
+    let mut object = BackupObject {
+      manifest: manifest.clone(),
+      collections: Vec::new(),
+    };
+    for collection in &collections {
+      object.collections.push(CollectionDump {
+        database: collection.database.clone(),
+        name: collection.name.clone(),
+        documents: collection.documents.clone(),
+      });
+    }
+    object.collections[0].documents = [1, 2, 4, 5, 3]
+      .into_iter()
+      .map(|id| rawdoc! { "_id": id })
+      .collect();
+
+    // Read back part by part, batch by batch, the backup is the same as one held in memory
+    for format in [
+      BackupFormat::Archive,
+      BackupFormat::Bson,
+      BackupFormat::Json,
+    ] {
+      let content = object::encode_collections(
+        &manifest,
+        &source,
+        format,
+        JsonMode::Canonical,
+        BackupCompression::Gzip,
+        None,
+      )
+      .unwrap();
+      let expected = object
+        .encode(format, JsonMode::Canonical, BackupCompression::Gzip, None)
+        .unwrap();
+      assert_eq!(content, expected);
+    }
+
+    clean_test_dir(test_dir_path);
+  }
+}
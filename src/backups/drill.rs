@@ -0,0 +1,183 @@
+use bson::{Document, doc};
+
+use crate::{
+  backups::{BackupCatalog, BackupObject, CollectionManifest, RestoreEngine, RestoreOptions},
+  datastores,
+  db::DatabaseConnection,
+  utils::{config::Backup, logger::Logger},
+};
+
+/// Restores the latest backup into a scratch database and checks it against its manifest.
+pub struct RestoreDrill<'a> {
+  name: &'a str,
+  backup: &'a Backup,
+}
+
+impl<'a> RestoreDrill<'a> {
+  pub fn new(name: &'a str, backup: &'a Backup) -> Self {
+    Self { name, backup }
+  }
+
+  /// Returns whether the drill passed. Errors are reserved for drills that could not run.
+  pub async fn run(&self) -> Result<bool, String> {
+    let drill = self
+      .backup
+      .restore_drill
+      .as_ref()
+      .ok_or(format!("No restore drill configured for {}", self.name))?;
+
+    let datastore = datastores::from_config(&self.backup.datastore)?;
+    let object_name = BackupCatalog::latest(datastore.as_ref(), self.name)?;
+    let object = BackupCatalog::load(
+      datastore.as_ref(),
+      &object_name,
+      self.backup.encryption_key.as_deref(),
+    )?;
+
+    let scratch_database = format!(
+      "mbm_drill_{}_{}",
+      self.name,
+      chrono::Local::now().timestamp()
+    );
+    Logger::info(&format!(
+      "Starting restore drill of {} into {}",
+      object_name, scratch_database
+    ));
+
+    let mut connection = DatabaseConnection::new();
+    connection
+      .connect(&drill.connection_string)
+      .await
+      .map_err(|err| format!("Cannot connect to drill database: {}", err))?;
+
+    let options = RestoreOptions {
+      connection_string: drill.connection_string.clone(),
+      target_database: Some(scratch_database),
+      drop: true,
+      indexes_first: false,
+      users_and_roles: false,
+    };
+    let res = self.check(&connection, &object, &options).await;
+
+    let databases = object.manifest.database_names();
+    for database in databases.iter().map(|d| options.target(&databases, d)) {
+      if let Ok(scratch) = connection.database(&database)
+        && let Err(err) = scratch.drop().await
+      {
+        Logger::warn(&format!("Cannot drop drill database {}: {}", database, err));
+      }
+    }
+    let _ = connection.disconnect().await;
+
+    let mismatches = res?;
+    if mismatches.is_empty() {
+      Logger::highlight(&format!("Restore drill of {} passed", object_name));
+    } else {
+      Logger::error(&format!("Restore drill of {} failed", object_name));
+      for mismatch in &mismatches {
+        Logger::error(&format!("  {}", mismatch));
+      }
+    }
+
+    Ok(mismatches.is_empty())
+  }
+
+  async fn check(
+    &self,
+    connection: &DatabaseConnection,
+    object: &BackupObject,
+    options: &RestoreOptions,
+  ) -> Result<Vec<String>, String> {
+    RestoreEngine::new(options).restore(object).await?;
+
+    let databases = object.manifest.database_names();
+    let mut mismatches = Vec::new();
+    for manifest in &object.manifest.collections {
+      let collection = connection
+        .database(&options.target(&databases, &manifest.database))
+        .map_err(|err| err.to_string())?
+        .collection::<Document>(&manifest.name);
+      let count = collection
+        .count_documents(doc! {})
+        .await
+        .map_err(|err| format!("Cannot count {}: {}", manifest.name, err))?;
+      let mut indexes = collection
+        .list_index_names()
+        .await
+        .map_err(|err| format!("Cannot list indexes of {}: {}", manifest.name, err))?;
+      indexes.sort();
+
+      mismatches.extend(Self::compare(manifest, count, &indexes));
+    }
+
+    Ok(mismatches)
+  }
+
+  fn compare(manifest: &CollectionManifest, count: u64, indexes: &[String]) -> Vec<String> {
+    let mut mismatches = Vec::new();
+
+    if count != manifest.document_count {
+      mismatches.push(format!(
+        "{}: expected {} documents, found {}",
+        manifest.namespace(),
+        manifest.document_count,
+        count
+      ));
+    }
+
+    let expected = manifest.index_names();
+    if expected != indexes {
+      mismatches.push(format!(
+        "{}: expected indexes [{}], found [{}]",
+        manifest.namespace(),
+        expected.join(", "),
+        indexes.join(", ")
+      ));
+    }
+
+    mismatches
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::doc;
+
+  use crate::backups::{CollectionManifest, IndexManifest, RestoreDrill};
+
+  fn manifest() -> CollectionManifest {
+    CollectionManifest {
+      database: "database".to_string(),
+      name: "users".to_string(),
+      document_count: 42,
+      indexes: vec![
+        IndexManifest {
+          name: "_id_".to_string(),
+          keys: doc! { "_id": 1 },
+          ..Default::default()
+        },
+        IndexManifest {
+          name: "email_1".to_string(),
+          keys: doc! { "email": 1 },
+          ..Default::default()
+        },
+      ],
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn drill_compare_matching() {
+    let indexes = ["_id_".to_string(), "email_1".to_string()];
+
+    assert!(RestoreDrill::compare(&manifest(), 42, &indexes).is_empty());
+  }
+
+  #[test]
+  fn drill_compare_mismatching() {
+    let indexes = ["_id_".to_string()];
+    let mismatches = RestoreDrill::compare(&manifest(), 41, &indexes);
+
+    assert_eq!(mismatches.len(), 2);
+  }
+}
@@ -0,0 +1,863 @@
+use std::{
+  collections::{HashMap, HashSet},
+  time::Duration,
+};
+
+use bson::{Document, doc};
+
+use crate::{
+  backups::{
+    BackupCatalog, BackupKind, BackupManifest, BackupObject, Checkpoint, CollectionDump,
+    CollectionManifest, CollectionSource, IndexManifest, ObjectName, OplogPosition, OplogState,
+    PartsSource, ShardingManifest, ViewManifest,
+    checkpoint::CheckpointPart,
+    gridfs,
+    masking::Sanitizer,
+    object,
+    parallel::{self, ReadBatch, ReadTask},
+    security,
+    selection::{self, CollectionFilter},
+  },
+  datastores::{self, Datastore, DedupStore, WriteLease},
+  db::{
+    DatabaseConnection, Throttle,
+    connection::{self, UNAUTHORIZED},
+  },
+  utils::{
+    config::{Backup, BackupCompression, BackupThrottle, CollectionQuery},
+    logger::Logger,
+  },
+};
+
+const OPLOG_DATABASE: &str = "local";
+const OPLOG_COLLECTION: &str = "oplog.rs";
+
+/// A dump whose documents are left in the parts of its checkpoint, but for those of the GridFS
+/// buckets, loaded in `object` to be checked and stored as a whole.
+struct Dump {
+  object: BackupObject,
+  checkpoint: Checkpoint,
+  /// Indexes of the collections loaded in `object`.
+  loaded: HashSet<usize>,
+}
+
+pub struct BackupEngine<'a> {
+  name: &'a str,
+  backup: &'a Backup,
+}
+
+impl<'a> BackupEngine<'a> {
+  pub fn new(name: &'a str, backup: &'a Backup) -> Self {
+    Self { name, backup }
+  }
+
+  /// Dumps the configured database into the datastore and returns the created object name.
+  pub async fn run(&self) -> Result<String, String> {
+    Logger::info(&format!("Starting backup {}", self.backup.display_name));
+
+    let datastore = datastores::from_config(&self.backup.datastore)?;
+    let mut connection = self.connection();
+    connection
+      .connect(&self.backup.connection_string)
+      .await
+      .map_err(|err| format!("Cannot connect to database: {}", err))?;
+
+    // Incremental backups replay the oplog from before the dump, as oplog entries are idempotent
+    let oplog_position = match connection.oplog_timestamp(true).await {
+      Ok(Some(timestamp)) => Some(OplogPosition::from(timestamp)),
+      _ => {
+        Logger::info("Cannot read the oplog, incremental backups will not be available");
+        None
+      }
+    };
+    let balancer_paused = self.backup.pause_balancer && self.stop_balancer(&connection).await;
+    let object = self
+      .dump(&connection, datastore.as_ref(), oplog_position)
+      .await;
+    if balancer_paused {
+      match connection.set_balancer(true).await {
+        Ok(()) => Logger::info("Restarted the balancer"),
+        Err(err) => Logger::error(&format!("Cannot restart the balancer: {}", err)),
+      }
+    }
+    let _ = connection.disconnect().await;
+    let Dump {
+      mut object,
+      checkpoint,
+      loaded,
+    } = object?;
+    let manifest = object.manifest.clone();
+
+    // Keeps garbage collection from deleting reused chunks and GridFS objects before the backups
+    // refer to them
+    let lease = WriteLease::acquire(datastore.clone()).await?;
+    if self.backup.gridfs_objects {
+      let stored = gridfs::store_files(
+        &mut object,
+        datastore.as_ref(),
+        self.backup.encryption_key.as_deref(),
+      )?;
+      Logger::info(&format!("Stored {} new GridFS file objects", stored));
+    }
+    // The backup is encoded as its documents are read back from the parts, one batch at a time
+    let source = PartsSource {
+      checkpoint: &checkpoint,
+      datastore: datastore.as_ref(),
+      backup_name: self.name,
+      compression: self.backup.compression,
+      encryption_key: self.backup.encryption_key.as_deref(),
+      loaded: loaded
+        .iter()
+        .map(|i| (*i, &object.collections[*i]))
+        .collect(),
+    };
+    let object_name = self.store(
+      datastore.as_ref(),
+      &object.manifest,
+      &source,
+      BackupKind::Full,
+    )?;
+    drop(source);
+    drop(object);
+
+    // The sanitized variant is masked from the parts of the dump, cleaned once it is stored
+    let sanitized = match self.backup.masking.is_empty() {
+      true => Ok(()),
+      false => self.store_sanitized(datastore.as_ref(), &checkpoint, &manifest),
+    };
+    drop(lease);
+    // The stored backup holds every part, an interrupted run has nothing left to resume
+    checkpoint.clean(datastore.as_ref(), self.name)?;
+
+    if let Some(position) = manifest.oplog_end {
+      OplogState {
+        base: object_name.clone(),
+        position,
+      }
+      .save(datastore.as_ref(), self.name)?;
+    }
+    sanitized?;
+
+    Logger::highlight(&format!(
+      "Backup {} done: {} documents from {} collections stored in {}",
+      self.backup.display_name,
+      manifest.document_count(),
+      manifest.collections.len(),
+      object_name
+    ));
+
+    // Failing to prune leaves extra backups, the new one is stored all the same
+    if let Some(keep) = self.backup.retention {
+      match BackupCatalog::prune(datastore.as_ref(), self.name, keep) {
+        Ok(_) => match BackupCatalog::collect_garbage(datastore.as_ref()) {
+          Ok((chunks, files)) => Logger::info(&format!(
+            "Deleted {chunks} unreferenced chunks and {files} GridFS objects"
+          )),
+          Err(err) => Logger::info(&format!("Skipping garbage collection: {}", err)),
+        },
+        Err(err) => Logger::error(&format!("Cannot prune backups of {}: {}", self.name, err)),
+      }
+    }
+
+    Ok(object_name)
+  }
+
+  /// Stores the oplog entries of the configured database written since the previous backup, and
+  /// returns the created object name, or `None` when the oplog did not move.
+  pub async fn run_incremental(&self) -> Result<Option<String>, String> {
+    Logger::info(&format!(
+      "Starting incremental backup {}",
+      self.backup.display_name
+    ));
+
+    let datastore = datastores::from_config(&self.backup.datastore)?;
+    let mut state = OplogState::load(datastore.as_ref(), self.name)?
+      .ok_or("No full backup recorded an oplog position, run a full backup first")?;
+
+    let mut connection = self.connection();
+    connection
+      .connect(&self.backup.connection_string)
+      .await
+      .map_err(|err| format!("Cannot connect to database: {}", err))?;
+
+    let object = self.tail_oplog(&connection, &state).await;
+    let _ = connection.disconnect().await;
+    let Some(object) = object? else {
+      Logger::info(&format!(
+        "No new oplog entries for {}, skipping incremental backup",
+        self.backup.display_name
+      ));
+      return Ok(None);
+    };
+
+    let lease = WriteLease::acquire(datastore.clone()).await?;
+    let object_name = self.store(
+      datastore.as_ref(),
+      &object.manifest,
+      &object.collections,
+      BackupKind::Incremental,
+    )?;
+    drop(lease);
+    state.position = object.manifest.oplog_end.unwrap_or(state.position);
+    state.save(datastore.as_ref(), self.name)?;
+
+    Logger::highlight(&format!(
+      "Incremental backup {} done: {} oplog entries stored in {}",
+      self.backup.display_name,
+      object.manifest.document_count(),
+      object_name
+    ));
+
+    Ok(Some(object_name))
+  }
+
+  /// Creates a connection applying the read preference and load limits of the backup.
+  fn connection(&self) -> DatabaseConnection {
+    let mut connection = DatabaseConnection::new();
+    if let Some(read_preference) = &self.backup.read_preference {
+      connection.set_read_preference(connection::read_preference(read_preference));
+    }
+
+    let throttle = &self.backup.throttle;
+    if *throttle != BackupThrottle::default() {
+      connection.set_throttle(Throttle::new(
+        throttle.documents_per_second,
+        throttle.bytes_per_second,
+        throttle.max_replication_lag.map(Duration::from_secs),
+      ));
+    }
+    connection
+  }
+
+  /// Stores a backup whose documents are read from `source`, holding no more than its encoded
+  /// content, and returns the created object name.
+  fn store(
+    &self,
+    datastore: &dyn Datastore,
+    manifest: &BackupManifest,
+    source: &dyn CollectionSource,
+    kind: BackupKind,
+  ) -> Result<String, String> {
+    let object_name = ObjectName {
+      directory: String::new(),
+      backup_name: self.name.to_string(),
+      timestamp: chrono::Local::now().timestamp(),
+      kind,
+      format: self.backup.format,
+      compression: self.backup.compression,
+      dedup: self.backup.dedup,
+    }
+    .to_string();
+    gridfs::store_references(manifest, source, datastore, &object_name)?;
+
+    if self.backup.dedup {
+      // Chunks are compressed and encrypted one by one, so that they match from one backup to
+      // the next
+      let content = object::encode_collections(
+        manifest,
+        source,
+        self.backup.format,
+        self.backup.json_mode,
+        BackupCompression::None,
+        None,
+      )?;
+      let stats = DedupStore::new(
+        datastore,
+        self.backup.compression,
+        self.backup.encryption_key.as_deref(),
+      )
+      .put(&object_name, &content)?;
+      Logger::info(&format!(
+        "Stored {} new chunks out of {}",
+        stats.stored, stats.chunks
+      ));
+      return Ok(object_name);
+    }
+
+    let content = object::encode_collections(
+      manifest,
+      source,
+      self.backup.format,
+      self.backup.json_mode,
+      self.backup.compression,
+      self.backup.encryption_key.as_deref(),
+    )?;
+    datastore.put_object(&object_name, &content)?;
+
+    Ok(object_name)
+  }
+
+  /// Stores the sanitized variant of a backup, built from the parts of its dump batch by batch so
+  /// that the backup is never held twice.
+  fn store_sanitized(
+    &self,
+    datastore: &dyn Datastore,
+    checkpoint: &Checkpoint,
+    manifest: &BackupManifest,
+  ) -> Result<(), String> {
+    let mut sanitizer = Sanitizer::new(
+      manifest,
+      &self.backup.masking,
+      self.backup.masking_secret.as_deref().unwrap_or_default(),
+    );
+    for (i, part) in checkpoint.parts.iter().enumerate() {
+      for batch in 0..part.batches.len() {
+        let documents = checkpoint.load_batch(
+          datastore,
+          self.name,
+          i,
+          batch,
+          self.backup.compression,
+          self.backup.encryption_key.as_deref(),
+        )?;
+        sanitizer.add(part.collection, documents)?;
+      }
+    }
+
+    // Problems of the buckets were reported along with the dump
+    let mut sanitized = sanitizer.finish();
+    gridfs::check_buckets(&mut sanitized.collections);
+    if self.backup.gridfs_objects {
+      gridfs::store_files(
+        &mut sanitized,
+        datastore,
+        self.backup.encryption_key.as_deref(),
+      )?;
+    }
+    let sanitized_name = self.store(
+      datastore,
+      &sanitized.manifest,
+      &sanitized.collections,
+      BackupKind::Sanitized,
+    )?;
+    Logger::info(&format!(
+      "Stored the sanitized variant of {} in {}",
+      self.backup.display_name, sanitized_name
+    ));
+    Ok(())
+  }
+
+  /// Reads the oplog segment following the recorded position, as an incremental backup object.
+  async fn tail_oplog(
+    &self,
+    connection: &DatabaseConnection,
+    state: &OplogState,
+  ) -> Result<Option<BackupObject>, String> {
+    let databases = selection::resolve_databases(connection, self.backup).await?;
+
+    let oplog_error = |err: mongodb::error::Error| format!("Cannot read the oplog: {}", err);
+    let (Some(oldest), Some(latest)) = (
+      connection
+        .oplog_timestamp(false)
+        .await
+        .map_err(oplog_error)?,
+      connection
+        .oplog_timestamp(true)
+        .await
+        .map_err(oplog_error)?,
+    ) else {
+      return Err("Incremental backups require a replica set".to_string());
+    };
+
+    if OplogPosition::from(oldest) > state.position {
+      return Err(
+        "The oplog no longer covers the last backup, run a full backup first".to_string(),
+      );
+    }
+    if OplogPosition::from(latest) <= state.position {
+      return Ok(None);
+    }
+
+    let entries = connection
+      .read_oplog(&databases, state.position.into(), latest)
+      .await
+      .map_err(oplog_error)?;
+
+    let mut manifest = BackupManifest::new(self.name, &self.backup.display_name);
+    manifest.kind = BackupKind::Incremental;
+    manifest.databases = databases;
+    manifest.base = Some(state.base.clone());
+    manifest.oplog_start = Some(state.position);
+    manifest.oplog_end = Some(latest.into());
+    manifest.collections.push(CollectionManifest {
+      database: OPLOG_DATABASE.to_string(),
+      name: OPLOG_COLLECTION.to_string(),
+      document_count: entries.len() as u64,
+      indexes: Vec::new(),
+      ..Default::default()
+    });
+
+    Ok(Some(BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: OPLOG_DATABASE.to_string(),
+        name: OPLOG_COLLECTION.to_string(),
+        documents: entries,
+      }],
+    }))
+  }
+
+  /// Stops the balancer of the sharded cluster, and tells whether it has to be restarted.
+  async fn stop_balancer(&self, connection: &DatabaseConnection) -> bool {
+    if !connection.is_mongos().await.unwrap_or(false) {
+      Logger::warn("The balancer can only be paused through a mongos, it is left running");
+      return false;
+    }
+
+    match connection.set_balancer(false).await {
+      Ok(()) => {
+        Logger::info("Stopped the balancer for the duration of the dump");
+        true
+      }
+      Err(err) => {
+        Logger::warn(&format!(
+          "Cannot stop the balancer, chunks may move during the dump: {}",
+          err
+        ));
+        false
+      }
+    }
+  }
+
+  /// Reads the shard keys of the sharded collections of the databases, by namespace.
+  async fn shard_keys(
+    &self,
+    connection: &DatabaseConnection,
+    databases: &[String],
+  ) -> Result<HashMap<String, ShardingManifest>, String> {
+    if !connection.is_mongos().await.unwrap_or(false) {
+      return Ok(HashMap::new());
+    }
+
+    let entries = connection
+      .sharded_collections(databases)
+      .await
+      .map_err(|err| format!("Cannot read the sharded collections: {}", err))?;
+    Ok(
+      entries
+        .iter()
+        .filter_map(|entry| {
+          let namespace = entry.get_str("_id").ok()?;
+          Some((namespace.to_string(), ShardingManifest::from_config(entry)?))
+        })
+        .collect(),
+    )
+  }
+
+  /// Adds the users and roles of the databases to the manifest, leaving them out when the
+  /// connected user is not allowed to read them.
+  async fn dump_users_and_roles(
+    &self,
+    connection: &DatabaseConnection,
+    databases: &[String],
+    manifest: &mut BackupManifest,
+  ) -> Result<(), String> {
+    let skip = |err: &mongodb::error::Error, kind: &str, database: &str| {
+      if connection::command_error_code(err) == Some(UNAUTHORIZED) {
+        Logger::warn(&format!(
+          "Not allowed to read the {} of {}, they are left out of the backup",
+          kind, database
+        ));
+        Ok(())
+      } else {
+        Err(format!("Cannot read the {} of {}: {}", kind, database, err))
+      }
+    };
+
+    for database in databases {
+      match connection.users_info(database).await {
+        Ok(users) => manifest
+          .users
+          .extend(users.iter().filter_map(security::system_user)),
+        Err(err) => skip(&err, "users", database)?,
+      }
+      match connection.roles_info(database).await {
+        Ok(roles) => manifest
+          .roles
+          .extend(roles.iter().filter_map(security::system_role)),
+        Err(err) => skip(&err, "roles", database)?,
+      }
+    }
+
+    Logger::info(&format!(
+      "Dumped {} users and {} roles",
+      manifest.users.len(),
+      manifest.roles.len()
+    ));
+    Ok(())
+  }
+
+  /// Splits a large collection into `_id` ranges read by several workers. Capped collections are
+  /// read whole to keep their natural order, and time-series collections have no `_id` index.
+  async fn read_filters(
+    &self,
+    connection: &DatabaseConnection,
+    collection: &CollectionManifest,
+    filter: Document,
+  ) -> Result<Vec<Document>, String> {
+    if self.backup.parallelism == 1 || collection.is_capped() || collection.is_timeseries() {
+      return Ok(vec![filter]);
+    }
+
+    let namespace = collection.namespace();
+    let count = connection
+      .estimated_count(&collection.database, &collection.name)
+      .await
+      .map_err(|err| format!("Cannot count documents of {}: {}", namespace, err))?;
+    let ranges = parallel::range_count(count, self.backup.parallelism);
+    if ranges == 1 {
+      return Ok(vec![filter]);
+    }
+
+    let ids = connection
+      .sample_ids(
+        &collection.database,
+        &collection.name,
+        filter.clone(),
+        ranges * parallel::SAMPLES_PER_RANGE,
+      )
+      .await
+      .map_err(|err| format!("Cannot sample {}: {}", namespace, err))?;
+    let filters = parallel::range_filters(&filter, &parallel::boundaries(&ids, ranges));
+    Logger::info(&format!(
+      "Reading {} in {} _id ranges",
+      namespace,
+      filters.len()
+    ));
+    Ok(filters)
+  }
+
+  /// Resumes the interrupted run of the backup when it read the same collections, keeping the
+  /// batches it stored and reading on from there. Otherwise, the objects of the
+  /// interrupted run are deleted and a new checkpoint is saved.
+  fn checkpoint(
+    &self,
+    datastore: &dyn Datastore,
+    manifest: &BackupManifest,
+    tasks: Vec<(usize, ReadTask)>,
+    oplog_position: Option<OplogPosition>,
+  ) -> Result<Checkpoint, String> {
+    let plan = Checkpoint::plan(manifest);
+    let interrupted = Checkpoint::load(datastore, self.name).unwrap_or_else(|err| {
+      Logger::warn(&format!(
+        "Ignoring the checkpoint of {}: {}",
+        self.name, err
+      ));
+      None
+    });
+
+    if let Some(mut checkpoint) = interrupted {
+      if checkpoint.plan == plan {
+        match self.resume(datastore, &mut checkpoint) {
+          Ok(()) => {
+            Logger::info(&format!(
+              "Resuming the interrupted run of {}: {} of {} parts already dumped",
+              self.backup.display_name,
+              checkpoint
+                .parts
+                .iter()
+                .filter(|part| part.documents.is_some())
+                .count(),
+              checkpoint.parts.len()
+            ));
+            return Ok(checkpoint);
+          }
+          Err(err) => Logger::warn(&format!(
+            "Cannot resume the interrupted run of {}, starting over: {}",
+            self.backup.display_name, err
+          )),
+        }
+      } else {
+        Logger::warn(&format!(
+          "The collections of {} changed since its interrupted run, starting over",
+          self.backup.display_name
+        ));
+      }
+      checkpoint.clean(datastore, self.name)?;
+    }
+
+    let parts = tasks
+      .into_iter()
+      .map(|(collection, read)| CheckpointPart {
+        collection,
+        read,
+        documents: None,
+        batches: Vec::new(),
+        last_id: None,
+      })
+      .collect();
+    let checkpoint = Checkpoint::new(plan, oplog_position, parts);
+    checkpoint.save(datastore, self.name)?;
+    Ok(checkpoint)
+  }
+
+  /// Keeps the parts of an interrupted run read entirely, and resumes the others after their last
+  /// stored batch.
+  fn resume(&self, datastore: &dyn Datastore, checkpoint: &mut Checkpoint) -> Result<(), String> {
+    checkpoint.check_parts(datastore, self.name)?;
+    for part in 0..checkpoint.parts.len() {
+      if checkpoint.parts[part].documents.is_none() {
+        checkpoint.resume_part(datastore, self.name, part)?;
+      }
+    }
+    checkpoint.save(datastore, self.name)
+  }
+
+  async fn dump(
+    &self,
+    connection: &DatabaseConnection,
+    datastore: &(dyn Datastore + Send + Sync),
+    oplog_position: Option<OplogPosition>,
+  ) -> Result<Dump, String> {
+    let databases = selection::resolve_databases(connection, self.backup).await?;
+    let filter = CollectionFilter::new(self.backup)?;
+    let mut shard_keys = self.shard_keys(connection, &databases).await?;
+
+    let mut manifest = BackupManifest::new(self.name, &self.backup.display_name);
+    let mut collections = Vec::new();
+    let mut buckets = HashSet::new();
+    let mut tasks = Vec::new();
+
+    let mut session = match connection.supports_snapshots().await {
+      Ok(true) => Some(
+        connection
+          .start_snapshot_session()
+          .await
+          .map_err(|err| format!("Cannot start snapshot session: {}", err))?,
+      ),
+      _ => {
+        Logger::warn(
+          "The server does not support snapshot reads, collections are dumped one after another \
+           and the backup may be inconsistent",
+        );
+        None
+      }
+    };
+
+    for database in &databases {
+      let names = connection
+        .list_collections(database)
+        .await
+        .map_err(|err| format!("Cannot list collections of {}: {}", database, err))?;
+
+      for name in names.iter().cloned() {
+        // Both collections of a GridFS bucket are selected by its files collection
+        let bucket = gridfs::bucket_name(&name, &names);
+        let selected_name = bucket.map_or(name.clone(), gridfs::files_collection);
+        if let Some(reason) = filter.skip_reason(database, &selected_name) {
+          Logger::info(&format!(
+            "Skipping collection {}.{}: {}",
+            database, name, reason
+          ));
+          continue;
+        }
+
+        // Filtering a bucket would leave files without their chunks
+        let query = match bucket {
+          Some(_) => CollectionQuery::default(),
+          None => selection::collection_setting(&self.backup.collections, database, &name)
+            .cloned()
+            .unwrap_or_default(),
+        };
+        if query != CollectionQuery::default() {
+          Logger::info(&format!(
+            "Backing up part of {}.{} with its configured filter and projection",
+            database, name
+          ));
+        }
+
+        let indexes = connection
+          .list_indexes(database, &name)
+          .await
+          .map_err(|err| format!("Cannot list indexes of {}.{}: {}", database, name, err))?
+          .iter()
+          .filter_map(IndexManifest::from_spec)
+          .collect();
+        let options = connection
+          .collection_options(database, &name)
+          .await
+          .map_err(|err| format!("Cannot read options of {}.{}: {}", database, name, err))?;
+
+        let collection = CollectionManifest {
+          database: database.to_string(),
+          name: name.clone(),
+          document_count: 0,
+          indexes,
+          options,
+          sharding: shard_keys.remove(&format!("{}.{}", database, name)),
+          gridfs_objects: false,
+          filter: query.filter.clone(),
+          projection: query.projection.clone(),
+        };
+        let filters = self
+          .read_filters(connection, &collection, query.filter.unwrap_or_default())
+          .await?;
+        // Reads go in `_id` order to resume after their last stored document, but for capped
+        // collections, kept in insertion order, and time-series ones, which have no `_id` index
+        let sort =
+          (!collection.is_capped() && !collection.is_timeseries()).then(|| doc! { "_id": 1 });
+        tasks.extend(filters.into_iter().map(|filter| {
+          (
+            manifest.collections.len(),
+            ReadTask {
+              database: database.to_string(),
+              collection: name.clone(),
+              filter,
+              projection: query.projection.clone(),
+              sort: sort.clone(),
+            },
+          )
+        }));
+
+        if bucket.is_some() {
+          buckets.insert(manifest.collections.len());
+        }
+        manifest.collections.push(collection);
+        collections.push(CollectionDump {
+          database: database.to_string(),
+          name,
+          documents: Vec::new(),
+        });
+      }
+
+      let views = connection
+        .list_views(database)
+        .await
+        .map_err(|err| format!("Cannot list views of {}: {}", database, err))?;
+      for view in views {
+        let Ok(name) = view.get_str("name") else {
+          continue;
+        };
+        if let Some(reason) = filter.skip_reason(database, name) {
+          Logger::info(&format!("Skipping view {}.{}: {}", database, name, reason));
+          continue;
+        }
+
+        manifest.views.push(ViewManifest {
+          database: database.to_string(),
+          name: name.to_string(),
+          options: view.get_document("options").cloned().unwrap_or_default(),
+        });
+      }
+    }
+
+    manifest.databases = databases.clone();
+    let mut checkpoint = self.checkpoint(datastore, &manifest, tasks, oplog_position)?;
+    let pending: Vec<usize> = (0..checkpoint.parts.len())
+      .filter(|i| checkpoint.parts[*i].documents.is_none())
+      .collect();
+    let resumed = checkpoint
+      .parts
+      .iter()
+      .any(|part| part.documents.is_some() || !part.batches.is_empty());
+    let tasks: Vec<ReadTask> = pending.iter().map(|i| checkpoint.next_read(*i)).collect();
+
+    // Workers read in sessions sharing the snapshot of this one, once the server picked its time
+    let snapshot_time = match (session.as_mut(), tasks.first()) {
+      (Some(session), Some(task)) => connection
+        .pin_snapshot(session, &task.database, &task.collection)
+        .await
+        .map_err(|err| format!("Cannot read {}.{}: {}", task.database, task.collection, err))?,
+      _ => None,
+    };
+    // Batches are stored as they are read, and only GridFS buckets are loaded back once every
+    // collection is read
+    parallel::read_all(
+      connection,
+      &tasks,
+      self.backup.parallelism,
+      snapshot_time,
+      |batch| {
+        let batch = ReadBatch {
+          task: pending[batch.task],
+          ..batch
+        };
+        checkpoint.store_batch(
+          datastore,
+          self.name,
+          &batch,
+          self.backup.compression,
+          self.backup.encryption_key.as_deref(),
+        )
+      },
+    )
+    .await?;
+    for (i, part) in checkpoint.parts.iter().enumerate() {
+      if !buckets.contains(&part.collection) {
+        continue;
+      }
+      let documents = checkpoint.load_part(
+        datastore,
+        self.name,
+        i,
+        self.backup.compression,
+        self.backup.encryption_key.as_deref(),
+      )?;
+      collections[part.collection].documents.extend(documents);
+    }
+
+    for (i, (collection, dump)) in manifest
+      .collections
+      .iter_mut()
+      .zip(&collections)
+      .enumerate()
+    {
+      collection.document_count = checkpoint.collection_documents(i);
+      let kind = if collection.is_timeseries() {
+        " time-series collection"
+      } else if collection.is_capped() {
+        " capped collection"
+      } else if collection.sharding.is_some() {
+        " sharded collection"
+      } else {
+        ""
+      };
+      Logger::info(&format!(
+        "Dumped {} documents from{} {}",
+        collection.document_count,
+        kind,
+        dump.namespace()
+      ));
+    }
+
+    if self.backup.users_and_roles {
+      self
+        .dump_users_and_roles(connection, &databases, &mut manifest)
+        .await?;
+    }
+
+    for problem in gridfs::check_buckets(&mut collections) {
+      Logger::warn(&format!("GridFS integrity check failed: {}", problem));
+    }
+
+    manifest.cluster_time = session
+      .and_then(|session| session.snapshot_time())
+      .map(OplogPosition::from)
+      .filter(|_| !resumed);
+    if let Some(cluster_time) = manifest.cluster_time {
+      Logger::info(&format!(
+        "Dumped a consistent snapshot at cluster time {}.{}",
+        cluster_time.time, cluster_time.increment
+      ));
+    } else if resumed {
+      Logger::warn(
+        "Collections of the resumed backup were read at different times, the backup may be \
+         inconsistent until the oplog replay of an incremental backup",
+      );
+    }
+    // A snapshot holds exactly the writes up to its cluster time, so the oplog resumes from there.
+    // Otherwise it resumes from before the first read of the dump, as oplog entries are idempotent.
+    manifest.oplog_end = checkpoint
+      .oplog_position
+      .map(|position| manifest.cluster_time.unwrap_or(position));
+
+    Ok(Dump {
+      object: BackupObject {
+        manifest,
+        collections,
+      },
+      checkpoint,
+      loaded: buckets,
+    })
+  }
+}
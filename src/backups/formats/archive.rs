@@ -0,0 +1,347 @@
+//! The `mongodump --archive` layout, so that backups can be restored with `mongorestore`.
+//!
+//! An archive starts with a magic number, a header document and one metadata document per
+//! collection, followed by a terminator. The body then contains, for each collection, a namespace
+//! header, the collection documents and a terminator, and finally an EOF namespace header holding
+//! the CRC-64 of the collection documents.
+
+use std::{collections::HashMap, io::Write};
+
+use bson::{Bson, Document, RawDocumentBuf, doc};
+
+use crate::backups::{
+  BackupManifest, BackupObject, CollectionDump, CollectionManifest, CollectionSource,
+  IndexManifest,
+  formats::{BsonReader, TERMINATOR, write_bytes, write_document},
+};
+
+const MAGIC_NUMBER: u32 = 0x8199e26d;
+const FORMAT_VERSION: &str = "0.1";
+/// Extra header field holding the mbm manifest, ignored by `mongorestore`.
+const MANIFEST_FIELD: &str = "mbm_manifest";
+const CRC64_ECMA_POLY: u64 = 0xc96c5795d7870f42;
+const CRC64_INITIAL: u64 = !0;
+
+/// Writes the archive as the documents are read from `source`.
+pub fn write(
+  manifest: &BackupManifest,
+  source: &dyn CollectionSource,
+  content: &mut dyn Write,
+) -> Result<(), String> {
+  write_bytes(content, &MAGIC_NUMBER.to_le_bytes())?;
+
+  let header =
+    serde_json::to_string(manifest).map_err(|err| format!("Cannot serialize manifest: {}", err))?;
+  write_document(
+    content,
+    &doc! {
+      "concurrent_collections": 1,
+      "version": FORMAT_VERSION,
+      "server_version": "",
+      "tool_version": format!("mbm {}", env!("CARGO_PKG_VERSION")),
+      MANIFEST_FIELD: header,
+    },
+  )?;
+
+  for (i, collection) in manifest.collections.iter().enumerate() {
+    write_document(
+      content,
+      &doc! {
+        "db": &collection.database,
+        "collection": &collection.name,
+        "metadata": collection_metadata(collection),
+        "size": source.count(i) as i64,
+        "type": "collection",
+      },
+    )?;
+  }
+  write_bytes(content, &TERMINATOR)?;
+
+  for (i, collection) in manifest.collections.iter().enumerate() {
+    let mut crc = CRC64_INITIAL;
+    if source.count(i) > 0 {
+      write_document(content, &namespace_header(collection, false, 0))?;
+      source.read_collection(i, &mut |documents| {
+        for document in documents {
+          crc = crc64_update(crc, document.as_bytes());
+          write_bytes(content, document.as_bytes())?;
+        }
+        Ok(())
+      })?;
+      write_bytes(content, &TERMINATOR)?;
+    }
+
+    write_document(content, &namespace_header(collection, true, !crc as i64))?;
+    write_bytes(content, &TERMINATOR)?;
+  }
+
+  Ok(())
+}
+
+pub fn decode(content: &[u8]) -> Result<BackupObject, String> {
+  let mut reader = BsonReader::new(content);
+
+  let magic = reader.read_bytes(4)?;
+  if magic != MAGIC_NUMBER.to_le_bytes() {
+    return Err("Not a mongodump archive".to_string());
+  }
+
+  let header = reader.read_document()?.ok_or("Missing archive header")?;
+
+  let mut metadata = Vec::new();
+  while let Some(document) = reader.read_document()? {
+    metadata.push(document);
+  }
+
+  let mut documents: HashMap<String, Vec<RawDocumentBuf>> = HashMap::new();
+  while !reader.is_empty() {
+    let namespace = reader
+      .read_document()?
+      .ok_or("Unexpected terminator in archive body")?;
+    let db = namespace.get_str("db").map_err(|err| err.to_string())?;
+    let collection = namespace
+      .get_str("collection")
+      .map_err(|err| err.to_string())?;
+    let entry = documents.entry(format!("{db}.{collection}")).or_default();
+
+    if namespace.get_bool("EOF").unwrap_or(false) {
+      if reader.read_document()?.is_some() {
+        return Err(format!("Missing terminator after {db}.{collection} EOF"));
+      }
+      continue;
+    }
+    while let Some(document) = reader.read_raw_document()? {
+      entry.push(document);
+    }
+  }
+
+  let manifest = match header.get_str(MANIFEST_FIELD) {
+    Ok(manifest) => {
+      serde_json::from_str(manifest).map_err(|err| format!("Invalid backup manifest: {}", err))?
+    }
+    Err(_) => derive_manifest(&metadata, &documents)?,
+  };
+
+  let collections = manifest
+    .collections
+    .iter()
+    .map(|collection| CollectionDump {
+      database: collection.database.clone(),
+      name: collection.name.clone(),
+      documents: documents
+        .remove(&collection.namespace())
+        .unwrap_or_default(),
+    })
+    .collect();
+
+  Ok(BackupObject {
+    manifest,
+    collections,
+  })
+}
+
+/// Builds a manifest for archives that were not created by mbm.
+fn derive_manifest(
+  metadata: &[Document],
+  documents: &HashMap<String, Vec<RawDocumentBuf>>,
+) -> Result<BackupManifest, String> {
+  let mut manifest = BackupManifest::new("", "");
+
+  for collection in metadata {
+    let database = collection.get_str("db").map_err(|err| err.to_string())?;
+    let name = collection
+      .get_str("collection")
+      .map_err(|err| err.to_string())?;
+    let (indexes, options) = match collection.get_str("metadata") {
+      Ok(metadata) => parse_metadata(metadata)?,
+      Err(_) => (Vec::new(), Document::new()),
+    };
+
+    manifest.collections.push(CollectionManifest {
+      database: database.to_string(),
+      name: name.to_string(),
+      document_count: documents
+        .get(&format!("{database}.{name}"))
+        .map_or(0, |d| d.len() as u64),
+      indexes,
+      options,
+      ..Default::default()
+    });
+  }
+
+  Ok(manifest)
+}
+
+/// Reads the index definitions and collection options from a `mongodump` collection metadata
+/// JSON.
+pub fn parse_metadata(metadata: &str) -> Result<(Vec<IndexManifest>, Document), String> {
+  let value: serde_json::Value = serde_json::from_str(metadata)
+    .map_err(|err| format!("Invalid collection metadata: {}", err))?;
+  let Ok(Bson::Document(metadata)) = Bson::try_from(value) else {
+    return Err("Invalid collection metadata".to_string());
+  };
+
+  let indexes = match metadata.get_array("indexes") {
+    Ok(indexes) => indexes
+      .iter()
+      .filter_map(|index| IndexManifest::from_spec(index.as_document()?))
+      .collect(),
+    Err(_) => Vec::new(),
+  };
+  let options = metadata
+    .get_document("options")
+    .cloned()
+    .unwrap_or_default();
+
+  Ok((indexes, options))
+}
+
+/// The collection metadata as written by `mongodump` in its `.metadata.json` files.
+fn collection_metadata(manifest: &CollectionManifest) -> String {
+  let indexes: Vec<Bson> = manifest
+    .indexes
+    .iter()
+    .map(|index| {
+      let mut spec = doc! { "v": 2 };
+      spec.extend(index.spec());
+      Bson::Document(spec)
+    })
+    .collect();
+
+  Bson::Document(doc! {
+    "indexes": indexes,
+    "collectionName": &manifest.name,
+    "type": "collection",
+    "options": manifest.options.clone(),
+  })
+  .into_canonical_extjson()
+  .to_string()
+}
+
+fn namespace_header(collection: &CollectionManifest, eof: bool, crc: i64) -> Document {
+  doc! {
+    "db": &collection.database,
+    "collection": &collection.name,
+    "EOF": eof,
+    "CRC": crc,
+  }
+}
+
+/// CRC-64 with the ECMA polynomial, as computed by Go's `hash/crc64` used by `mongodump`. Updates
+/// the CRC of the content so far, which is inverted once all of it is read.
+fn crc64_update(mut crc: u64, content: &[u8]) -> u64 {
+  for byte in content {
+    crc ^= *byte as u64;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 {
+        (crc >> 1) ^ CRC64_ECMA_POLY
+      } else {
+        crc >> 1
+      };
+    }
+  }
+  crc
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{doc, oid::ObjectId, rawdoc};
+
+  use crate::{
+    backups::{
+      BackupManifest, BackupObject, CollectionDump, CollectionManifest, IndexManifest,
+      formats::archive::{CRC64_INITIAL, crc64_update, decode},
+    },
+    utils::config::{BackupCompression, BackupFormat, JsonMode},
+  };
+
+  fn sample_object() -> BackupObject {
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    let mut collections = Vec::new();
+
+    for (name, documents) in [
+      (
+        "users",
+        vec![
+          rawdoc! { "_id": ObjectId::new(), "name": "Nolhan" },
+          rawdoc! { "_id": ObjectId::new(), "name": "ValDesign" },
+        ],
+      ),
+      ("empty", vec![]),
+    ] {
+      manifest.collections.push(CollectionManifest {
+        database: "database".to_string(),
+        name: name.to_string(),
+        document_count: documents.len() as u64,
+        indexes: vec![IndexManifest {
+          name: "_id_".to_string(),
+          keys: doc! { "_id": 1 },
+          ..Default::default()
+        }],
+        ..Default::default()
+      });
+      collections.push(CollectionDump {
+        database: "database".to_string(),
+        name: name.to_string(),
+        documents,
+      });
+    }
+
+    BackupObject {
+      manifest,
+      collections,
+    }
+  }
+
+  #[test]
+  fn archive_crc64() {
+    // Check value of CRC-64/XZ, which Go's crc64.ECMA table implements
+    assert_eq!(
+      !crc64_update(CRC64_INITIAL, b"123456789"),
+      0x995dc9bbdf1939fa
+    );
+
+    // The CRC of content read in parts is the same
+    let crc = crc64_update(CRC64_INITIAL, b"1234");
+    assert_eq!(!crc64_update(crc, b"56789"), 0x995dc9bbdf1939fa);
+  }
+
+  #[test]
+  fn archive_roundtrip() {
+    let object = sample_object();
+    let content = object
+      .encode(
+        BackupFormat::Archive,
+        JsonMode::Canonical,
+        BackupCompression::None,
+        None,
+      )
+      .unwrap();
+
+    assert_eq!(&content[..4], &[0x6d, 0xe2, 0x99, 0x81]);
+
+    let decoded = decode(&content).unwrap();
+    assert_eq!(decoded.manifest, object.manifest);
+    assert_eq!(decoded.collections.len(), 2);
+    assert_eq!(
+      decoded.collections[0].documents,
+      object.collections[0].documents
+    );
+    assert!(decoded.collections[1].documents.is_empty());
+  }
+
+  #[test]
+  fn archive_decode_invalid() {
+    assert!(decode(b"{}").is_err());
+
+    let content = sample_object()
+      .encode(
+        BackupFormat::Archive,
+        JsonMode::Canonical,
+        BackupCompression::None,
+        None,
+      )
+      .unwrap();
+    assert!(decode(&content[..content.len() - 10]).is_err());
+  }
+}
@@ -0,0 +1,183 @@
+//! Raw BSON stream, preserving the exact bytes of every document.
+//!
+//! The stream starts with a header document holding the manifest, followed, for each collection
+//! of the manifest, by a namespace document with the number of documents and the documents
+//! themselves. As BSON documents are length-prefixed, no other framing is needed.
+
+use std::io::Write;
+
+use bson::{Document, doc};
+
+use crate::backups::{
+  BackupManifest, BackupObject, CollectionDump, CollectionSource,
+  formats::{BsonReader, write_bytes, write_document},
+};
+
+const MANIFEST_FIELD: &str = "mbm_manifest";
+
+/// Writes the stream as the documents are read from `source`.
+pub fn write(
+  manifest: &BackupManifest,
+  source: &dyn CollectionSource,
+  content: &mut dyn Write,
+) -> Result<(), String> {
+  let header =
+    serde_json::to_string(manifest).map_err(|err| format!("Cannot serialize manifest: {}", err))?;
+  write_document(content, &doc! { MANIFEST_FIELD: header })?;
+
+  for (i, collection) in manifest.collections.iter().enumerate() {
+    write_document(
+      content,
+      &doc! {
+        "db": &collection.database,
+        "collection": &collection.name,
+        "count": source.count(i) as i64,
+      },
+    )?;
+
+    source.read_collection(i, &mut |documents| {
+      for document in documents {
+        write_bytes(content, document.as_bytes())?;
+      }
+      Ok(())
+    })?;
+  }
+
+  Ok(())
+}
+
+pub fn decode(content: &[u8]) -> Result<BackupObject, String> {
+  let mut reader = BsonReader::new(content);
+
+  let header = read_document(&mut reader)?;
+  let manifest: BackupManifest = serde_json::from_str(
+    header
+      .get_str(MANIFEST_FIELD)
+      .map_err(|_| "BSON stream has no manifest")?,
+  )
+  .map_err(|err| format!("Invalid backup manifest: {}", err))?;
+
+  let mut collections = Vec::new();
+  for collection in &manifest.collections {
+    let namespace = read_document(&mut reader)?;
+    if namespace.get_str("db").ok() != Some(collection.database.as_str())
+      || namespace.get_str("collection").ok() != Some(collection.name.as_str())
+    {
+      return Err(format!(
+        "Expected documents of {}, found {}",
+        collection.namespace(),
+        namespace
+      ));
+    }
+
+    let count = namespace
+      .get_i64("count")
+      .map_err(|err| format!("Invalid namespace header: {}", err))?;
+    let mut documents = Vec::new();
+    for _ in 0..count {
+      documents.push(
+        reader
+          .read_raw_document()?
+          .ok_or("Unexpected terminator in BSON stream")?,
+      );
+    }
+
+    collections.push(CollectionDump {
+      database: collection.database.clone(),
+      name: collection.name.clone(),
+      documents,
+    });
+  }
+
+  if !reader.is_empty() {
+    return Err("Unexpected content after the last collection".to_string());
+  }
+
+  Ok(BackupObject {
+    manifest,
+    collections,
+  })
+}
+
+fn read_document(reader: &mut BsonReader) -> Result<Document, String> {
+  reader
+    .read_document()?
+    .ok_or("Unexpected terminator in BSON stream".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{Binary, DateTime, Decimal128, doc, oid::ObjectId, rawdoc, spec::BinarySubtype};
+
+  use crate::{
+    backups::{
+      BackupManifest, BackupObject, CollectionDump, CollectionManifest,
+      formats::bson_stream::decode,
+    },
+    utils::config::{BackupCompression, BackupFormat, JsonMode},
+  };
+
+  #[test]
+  fn bson_stream_roundtrip_is_byte_for_byte() {
+    let documents = vec![
+      rawdoc! {
+        "_id": ObjectId::new(),
+        "int32": 42,
+        "int64": 42i64,
+        "decimal": Decimal128::from_bytes([1; 16]),
+        "date": DateTime::from_millis(1_760_000_000_000),
+        "uuid": Binary { subtype: BinarySubtype::Uuid, bytes: vec![7; 16] },
+        "custom": Binary { subtype: BinarySubtype::UserDefined(0x80), bytes: vec![1, 2, 3] },
+      },
+      rawdoc! { "b": 1, "a": 2 },
+    ];
+
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest.collections.push(CollectionManifest {
+      database: "database".to_string(),
+      name: "types".to_string(),
+      document_count: 2,
+      indexes: vec![],
+      ..Default::default()
+    });
+    let object = BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: "database".to_string(),
+        name: "types".to_string(),
+        documents,
+      }],
+    };
+
+    let decoded = decode(
+      &object
+        .encode(
+          BackupFormat::Bson,
+          JsonMode::Canonical,
+          BackupCompression::None,
+          None,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert_eq!(decoded.manifest, object.manifest);
+    for (decoded, original) in decoded.collections[0]
+      .documents
+      .iter()
+      .zip(&object.collections[0].documents)
+    {
+      assert_eq!(decoded.as_bytes(), original.as_bytes());
+    }
+  }
+
+  #[test]
+  fn bson_stream_decode_invalid() {
+    assert!(decode(b"").is_err());
+
+    let mut content = Vec::new();
+    doc! { "mbm_manifest": "{}" }
+      .to_writer(&mut content)
+      .unwrap();
+    assert!(decode(&content).is_err());
+  }
+}
@@ -0,0 +1,321 @@
+use std::io::Write;
+
+use bson::{Bson, Document, RawDocumentBuf};
+use serde_json::{Map, Value};
+
+use crate::{
+  backups::{BackupManifest, BackupObject, CollectionDump, CollectionSource, formats::write_bytes},
+  utils::config::JsonMode,
+};
+
+/// Serializes documents as Extended JSON v2. Canonical documents are parsed back into identical
+/// BSON, both modes can be decoded.
+/// Writes the backup as the documents are read from `source`, one document at a time.
+pub fn write(
+  manifest: &BackupManifest,
+  source: &dyn CollectionSource,
+  mode: JsonMode,
+  content: &mut dyn Write,
+) -> Result<(), String> {
+  let json_error = |err: serde_json::Error| format!("Cannot serialize backup: {}", err);
+
+  write_bytes(content, b"{\"manifest\":")?;
+  serde_json::to_writer(&mut *content, manifest)
+    .map_err(|err| format!("Cannot serialize manifest: {}", err))?;
+  write_bytes(content, b",\"data\":{")?;
+
+  for (i, collection) in manifest.collections.iter().enumerate() {
+    if i > 0 {
+      write_bytes(content, b",")?;
+    }
+    serde_json::to_writer(&mut *content, &collection.namespace()).map_err(json_error)?;
+    write_bytes(content, b":[")?;
+
+    let mut first = true;
+    source.read_collection(i, &mut |documents| {
+      for document in documents {
+        let document = Document::try_from(document.as_ref())
+          .map(|doc| match mode {
+            JsonMode::Canonical => canonical_extjson(Bson::Document(doc)),
+            JsonMode::Relaxed => Bson::Document(doc).into_relaxed_extjson(),
+          })
+          .map_err(|err| format!("Invalid document in {}: {}", collection.namespace(), err))?;
+        if !first {
+          write_bytes(content, b",")?;
+        }
+        first = false;
+        serde_json::to_writer(&mut *content, &document).map_err(json_error)?;
+      }
+      Ok(())
+    })?;
+    write_bytes(content, b"]")?;
+  }
+
+  write_bytes(content, b"}}")
+}
+
+/// Canonical Extended JSON, writing every double as a `$numberDouble` string. The bson crate
+/// falls back to plain JSON numbers for subnormal doubles, which do not parse back to the same
+/// value.
+fn canonical_extjson(value: Bson) -> Value {
+  match value {
+    Bson::Double(double) => {
+      let value = if double.is_nan() {
+        "NaN".to_string()
+      } else if double.is_infinite() {
+        if double.is_sign_negative() {
+          "-Infinity".to_string()
+        } else {
+          "Infinity".to_string()
+        }
+      } else {
+        let mut value = double.to_string();
+        if !value.contains('.') {
+          value.push_str(".0");
+        }
+        value
+      };
+
+      let mut wrapper = Map::new();
+      wrapper.insert("$numberDouble".to_string(), Value::String(value));
+      Value::Object(wrapper)
+    }
+    Bson::Array(values) => Value::Array(values.into_iter().map(canonical_extjson).collect()),
+    Bson::Document(doc) => Value::Object(
+      doc
+        .into_iter()
+        .map(|(key, value)| (key, canonical_extjson(value)))
+        .collect(),
+    ),
+    Bson::JavaScriptCodeWithScope(code) => {
+      let mut wrapper = Map::new();
+      wrapper.insert("$code".to_string(), Value::String(code.code));
+      wrapper.insert(
+        "$scope".to_string(),
+        canonical_extjson(Bson::Document(code.scope)),
+      );
+      Value::Object(wrapper)
+    }
+    other => other.into_canonical_extjson(),
+  }
+}
+
+pub fn decode(content: &[u8]) -> Result<BackupObject, String> {
+  let mut root: Map<String, Value> =
+    serde_json::from_slice(content).map_err(|err| format!("Invalid backup content: {}", err))?;
+
+  let manifest: BackupManifest = serde_json::from_value(
+    root
+      .remove("manifest")
+      .ok_or("Backup content has no manifest")?,
+  )
+  .map_err(|err| format!("Invalid backup manifest: {}", err))?;
+
+  let mut data = match root.remove("data") {
+    Some(Value::Object(data)) => data,
+    _ => return Err("Backup content has no data".to_string()),
+  };
+
+  let mut collections = Vec::new();
+  for collection in &manifest.collections {
+    let documents = match data.remove(&collection.namespace()) {
+      Some(Value::Array(documents)) => documents,
+      _ => return Err(format!("Missing data for {}", collection.namespace())),
+    };
+
+    let documents = documents
+      .into_iter()
+      .map(|value| match Bson::try_from(value) {
+        Ok(Bson::Document(doc)) => {
+          RawDocumentBuf::try_from(&doc).map_err(|err| format!("Invalid document: {}", err))
+        }
+        Ok(other) => Err(format!("Expected document, found {}", other)),
+        Err(err) => Err(format!("Invalid document: {}", err)),
+      })
+      .collect::<Result<_, _>>()?;
+
+    collections.push(CollectionDump {
+      database: collection.database.clone(),
+      name: collection.name.clone(),
+      documents,
+    });
+  }
+
+  Ok(BackupObject {
+    manifest,
+    collections,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use std::str::FromStr;
+
+  use bson::{
+    Binary, Bson, DateTime, Decimal128, Document, JavaScriptCodeWithScope, RawDocumentBuf, Regex,
+    Timestamp, doc, oid::ObjectId, raw::CString, spec::BinarySubtype,
+  };
+  use proptest::prelude::*;
+
+  use crate::{
+    backups::{
+      BackupManifest, BackupObject, CollectionDump, CollectionManifest, formats::json::decode,
+    },
+    utils::config::{BackupCompression, BackupFormat, JsonMode},
+  };
+
+  fn object_with(documents: Vec<RawDocumentBuf>) -> BackupObject {
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest.collections.push(CollectionManifest {
+      database: "database".to_string(),
+      name: "types".to_string(),
+      document_count: documents.len() as u64,
+      indexes: vec![],
+      ..Default::default()
+    });
+
+    BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: "database".to_string(),
+        name: "types".to_string(),
+        documents,
+      }],
+    }
+  }
+
+  fn key() -> impl Strategy<Value = String> {
+    "[a-z][a-z0-9_]{0,8}"
+  }
+
+  fn cstring() -> impl Strategy<Value = CString> {
+    "[^\u{0}]{0,12}".prop_map(|s| CString::try_from(s).unwrap())
+  }
+
+  fn scalar() -> impl Strategy<Value = Bson> {
+    prop_oneof![
+      // Extended JSON has a single NaN, payloads and sign of NaNs cannot be preserved
+      any::<f64>().prop_map(|d| Bson::Double(if d.is_nan() { f64::NAN } else { d })),
+      any::<String>().prop_map(Bson::String),
+      any::<bool>().prop_map(Bson::Boolean),
+      Just(Bson::Null),
+      (
+        cstring(),
+        proptest::sample::subsequence(vec!['i', 'l', 'm', 's', 'u', 'x'], 0..=6)
+      )
+        .prop_map(|(pattern, options)| {
+          Bson::RegularExpression(Regex {
+            pattern,
+            options: CString::try_from(options.into_iter().collect::<String>()).unwrap(),
+          })
+        }),
+      any::<String>().prop_map(Bson::JavaScriptCode),
+      any::<i32>().prop_map(Bson::Int32),
+      any::<i64>().prop_map(Bson::Int64),
+      (any::<u32>(), any::<u32>())
+        .prop_map(|(time, increment)| Bson::Timestamp(Timestamp { time, increment })),
+      (any::<u8>(), proptest::collection::vec(any::<u8>(), 0..32)).prop_map(|(subtype, bytes)| {
+        Bson::Binary(Binary {
+          subtype: BinarySubtype::from(subtype),
+          bytes,
+        })
+      }),
+      any::<[u8; 12]>().prop_map(|bytes| Bson::ObjectId(ObjectId::from_bytes(bytes))),
+      any::<i64>().prop_map(|millis| Bson::DateTime(DateTime::from_millis(millis))),
+      any::<String>().prop_map(Bson::Symbol),
+      (any::<i64>(), -6000i32..6000).prop_map(|(coefficient, exponent)| {
+        Bson::Decimal128(Decimal128::from_str(&format!("{coefficient}E{exponent}")).unwrap())
+      }),
+      Just(Bson::Undefined),
+      Just(Bson::MaxKey),
+      Just(Bson::MinKey),
+      ("[a-z]{1,8}\\.[a-z]{1,8}", any::<[u8; 12]>()).prop_map(|(namespace, id)| {
+        let pointer = serde_json::json!({
+          "$dbPointer": {
+            "$ref": namespace,
+            "$id": { "$oid": ObjectId::from_bytes(id).to_hex() },
+          }
+        });
+        Bson::try_from(pointer).unwrap()
+      }),
+    ]
+  }
+
+  fn value() -> impl Strategy<Value = Bson> {
+    scalar().prop_recursive(3, 32, 6, |inner| {
+      prop_oneof![
+        proptest::collection::vec(inner.clone(), 0..6).prop_map(Bson::Array),
+        proptest::collection::vec((key(), inner.clone()), 0..6)
+          .prop_map(|fields| Bson::Document(fields.into_iter().collect())),
+        (
+          any::<String>(),
+          proptest::collection::vec((key(), inner), 0..4)
+        )
+          .prop_map(|(code, fields)| {
+            Bson::JavaScriptCodeWithScope(JavaScriptCodeWithScope {
+              code,
+              scope: fields.into_iter().collect(),
+            })
+          }),
+      ]
+    })
+  }
+
+  fn document() -> impl Strategy<Value = Document> {
+    proptest::collection::vec((key(), value()), 0..8)
+      .prop_map(|fields| fields.into_iter().collect())
+  }
+
+  proptest! {
+    #[test]
+    fn json_canonical_roundtrip(documents in proptest::collection::vec(document(), 1..4)) {
+      let documents: Vec<RawDocumentBuf> = documents
+        .iter()
+        .map(|doc| RawDocumentBuf::try_from(doc).unwrap())
+        .collect();
+      let object = object_with(documents);
+
+      let decoded = decode(&object.encode(BackupFormat::Json, JsonMode::Canonical, BackupCompression::None, None).unwrap()).unwrap();
+      for (decoded, original) in decoded.collections[0]
+        .documents
+        .iter()
+        .zip(&object.collections[0].documents)
+      {
+        prop_assert_eq!(decoded.as_bytes(), original.as_bytes());
+      }
+    }
+  }
+
+  #[test]
+  fn json_canonical_type_wrappers() {
+    let object = object_with(vec![
+      RawDocumentBuf::try_from(&doc! { "int64": 42i64, "int32": 42, "double": 1.0 }).unwrap(),
+    ]);
+
+    let content = String::from_utf8(
+      object
+        .encode(
+          BackupFormat::Json,
+          JsonMode::Canonical,
+          BackupCompression::None,
+          None,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(content.contains(r#"{"int64":{"$numberLong":"42"},"int32":{"$numberInt":"42"},"double":{"$numberDouble":"1.0"}}"#));
+
+    let content = String::from_utf8(
+      object
+        .encode(
+          BackupFormat::Json,
+          JsonMode::Relaxed,
+          BackupCompression::None,
+          None,
+        )
+        .unwrap(),
+    )
+    .unwrap();
+    assert!(content.contains(r#"{"int64":42,"int32":42,"double":1.0}"#));
+  }
+}
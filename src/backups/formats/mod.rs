@@ -0,0 +1,75 @@
+use std::io::Write;
+
+use bson::{Document, RawDocumentBuf};
+
+pub mod archive;
+pub mod bson_stream;
+pub mod json;
+
+/// Marks the end of a documents sequence in archives.
+pub const TERMINATOR: [u8; 4] = [0xff; 4];
+
+pub fn write_document(content: &mut dyn Write, document: &Document) -> Result<(), String> {
+  document
+    .to_writer(content)
+    .map_err(|err| format!("Cannot serialize document: {}", err))
+}
+
+pub fn write_bytes(content: &mut dyn Write, bytes: &[u8]) -> Result<(), String> {
+  content
+    .write_all(bytes)
+    .map_err(|err| format!("Cannot write backup: {}", err))
+}
+
+/// Reads consecutive BSON documents from a buffer.
+pub struct BsonReader<'a> {
+  content: &'a [u8],
+  offset: usize,
+}
+
+impl<'a> BsonReader<'a> {
+  pub fn new(content: &'a [u8]) -> Self {
+    Self { content, offset: 0 }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.offset >= self.content.len()
+  }
+
+  pub fn read_bytes(&mut self, len: usize) -> Result<&[u8], String> {
+    let bytes = self
+      .content
+      .get(self.offset..self.offset + len)
+      .ok_or("Unexpected end of content")?;
+    self.offset += len;
+    Ok(bytes)
+  }
+
+  /// Reads the next document, or `None` when reaching a terminator.
+  pub fn read_document(&mut self) -> Result<Option<Document>, String> {
+    self
+      .read_raw_document()?
+      .map(|document| {
+        Document::try_from(document).map_err(|err| format!("Invalid document: {}", err))
+      })
+      .transpose()
+  }
+
+  pub fn read_raw_document(&mut self) -> Result<Option<RawDocumentBuf>, String> {
+    let len = self.read_bytes(4)?;
+    if len == TERMINATOR {
+      return Ok(None);
+    }
+
+    let len = i32::from_le_bytes([len[0], len[1], len[2], len[3]]);
+    if len < 5 {
+      return Err(format!("Invalid document size {}", len));
+    }
+    self.offset -= 4;
+
+    let bytes = self.read_bytes(len as usize)?;
+    RawDocumentBuf::from_bytes(bytes.to_vec())
+      .map(Some)
+      .map_err(|err| format!("Invalid document: {}", err))
+  }
+}
@@ -0,0 +1,655 @@
+//! GridFS buckets are backed up as a unit: the `<bucket>.files` and `<bucket>.chunks` collections
+//! are selected together, their chunks are checked against the files they belong to and kept in
+//! file and chunk order. File contents can be stored as individual `gridfs_<hash>` objects,
+//! shared by every backup holding the same file, and listed by a `<backup object>.gridfs`
+//! references object stored along with every backup holding them.
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use bson::{Binary, Bson, Document, RawDocumentBuf, spec::BinarySubtype};
+use sha2::{Digest, Sha256};
+
+use crate::{
+  backups::{BackupManifest, BackupObject, CollectionDump, CollectionSource},
+  datastores::{self, Datastore, GcLock},
+  utils::crypto,
+};
+
+const FILES_SUFFIX: &str = ".files";
+const CHUNKS_SUFFIX: &str = ".chunks";
+/// Fields replacing the `data` of the chunks whose file is stored as its own object.
+const OBJECT_FIELD: &str = "mbmObject";
+const OFFSET_FIELD: &str = "mbmOffset";
+const SUBTYPE_FIELD: &str = "mbmSubtype";
+const OBJECT_PREFIX: &str = "gridfs_";
+/// Extension of the objects listing the GridFS objects a backup object refers to.
+pub const REFERENCES_EXTENSION: &str = ".gridfs";
+
+/// Returns the bucket a collection belongs to, when both collections of the bucket exist.
+pub fn bucket_name<'a>(collection: &'a str, collections: &[String]) -> Option<&'a str> {
+  let bucket = collection
+    .strip_suffix(FILES_SUFFIX)
+    .or_else(|| collection.strip_suffix(CHUNKS_SUFFIX))?;
+
+  let exists = |suffix| {
+    collections
+      .iter()
+      .any(|c| *c == format!("{bucket}{suffix}"))
+  };
+  (exists(FILES_SUFFIX) && exists(CHUNKS_SUFFIX)).then_some(bucket)
+}
+
+pub fn files_collection(bucket: &str) -> String {
+  format!("{bucket}{FILES_SUFFIX}")
+}
+
+pub fn chunks_collection(bucket: &str) -> String {
+  format!("{bucket}{CHUNKS_SUFFIX}")
+}
+
+/// Checks that every file of a bucket has all its chunks, each holding the expected number of
+/// bytes, and returns the problems found.
+pub fn check_bucket(files: &[RawDocumentBuf], chunks: &[RawDocumentBuf]) -> Vec<String> {
+  let mut problems = Vec::new();
+  let mut file_chunks: HashMap<String, Vec<(i64, usize)>> = HashMap::new();
+
+  for chunk in chunks {
+    let (Some(file_id), Some(n), Ok(data)) = (
+      field_key(chunk, "files_id"),
+      integer(chunk, "n"),
+      chunk.get_binary("data"),
+    ) else {
+      problems.push("Chunk without files_id, n or data".to_string());
+      continue;
+    };
+    file_chunks
+      .entry(file_id)
+      .or_default()
+      .push((n, data.bytes.len()));
+  }
+
+  for file in files {
+    let (Some(id), Some(length), Some(chunk_size)) = (
+      field_key(file, "_id"),
+      integer(file, "length"),
+      integer(file, "chunkSize"),
+    ) else {
+      problems.push("File without _id, length or chunkSize".to_string());
+      continue;
+    };
+
+    let mut chunks = file_chunks.remove(&id).unwrap_or_default();
+    chunks.sort();
+    let count = if chunk_size > 0 {
+      (length + chunk_size - 1) / chunk_size
+    } else {
+      0
+    };
+
+    if chunks.len() as i64 != count || chunks.iter().zip(0..).any(|((n, _), i)| *n != i) {
+      problems.push(format!(
+        "File {id} has chunks {:?} instead of {count}",
+        chunks.iter().map(|(n, _)| n).collect::<Vec<_>>()
+      ));
+      continue;
+    }
+    for (n, size) in chunks {
+      let expected = chunk_size.min(length - n * chunk_size);
+      if size as i64 != expected {
+        problems.push(format!(
+          "Chunk {n} of file {id} holds {size} bytes instead of {expected}"
+        ));
+      }
+    }
+  }
+
+  for id in file_chunks.keys() {
+    problems.push(format!("Chunks of unknown file {id}"));
+  }
+  problems
+}
+
+/// Sorts the chunks and checks the integrity of every GridFS bucket among dumped collections, and
+/// returns the problems found.
+pub fn check_buckets(collections: &mut [CollectionDump]) -> Vec<String> {
+  let names: Vec<String> = collections.iter().map(|c| c.namespace()).collect();
+  let mut problems = Vec::new();
+
+  for bucket in names
+    .iter()
+    .filter_map(|name| name.strip_suffix(FILES_SUFFIX))
+  {
+    let position = |name: String| names.iter().position(|n| *n == name);
+    let (Some(files), Some(chunks)) = (
+      position(files_collection(bucket)),
+      position(chunks_collection(bucket)),
+    ) else {
+      continue;
+    };
+
+    let files = collections[files].documents.clone();
+    sort_chunks(&files, &mut collections[chunks].documents);
+    problems.extend(
+      check_bucket(&files, &collections[chunks].documents)
+        .into_iter()
+        .map(|problem| format!("{bucket}: {problem}")),
+    );
+  }
+  problems
+}
+
+/// Sorts chunks in the order of their files, then by chunk number, leaving chunks of unknown
+/// files at the end.
+pub fn sort_chunks(files: &[RawDocumentBuf], chunks: &mut [RawDocumentBuf]) {
+  let positions: HashMap<String, usize> = files
+    .iter()
+    .enumerate()
+    .filter_map(|(position, file)| Some((field_key(file, "_id")?, position)))
+    .collect();
+
+  chunks.sort_by_cached_key(|chunk| {
+    let position = field_key(chunk, "files_id")
+      .and_then(|id| positions.get(&id).copied())
+      .unwrap_or(usize::MAX);
+    (position, integer(chunk, "n").unwrap_or(i64::MAX))
+  });
+}
+
+/// Moves the file contents of the GridFS buckets of a backup to their own objects, leaving the
+/// chunks with a reference to them. Objects already stored by a previous backup are reused, so a
+/// `WriteLease` must be held until the references of the backup are stored. Returns the number of
+/// objects stored.
+pub fn store_files(
+  object: &mut BackupObject,
+  datastore: &dyn Datastore,
+  encryption_key: Option<&str>,
+) -> Result<usize, String> {
+  let mut stored = 0;
+  let names: Vec<String> = object.collections.iter().map(|c| c.namespace()).collect();
+
+  for (manifest, dump) in object
+    .manifest
+    .collections
+    .iter_mut()
+    .zip(object.collections.iter_mut())
+  {
+    let is_chunks =
+      manifest.name.ends_with(CHUNKS_SUFFIX) && bucket_name(&dump.namespace(), &names).is_some();
+    if !is_chunks || manifest.gridfs_objects {
+      continue;
+    }
+
+    let mut documents = Vec::with_capacity(dump.documents.len());
+    for file_chunks in dump
+      .documents
+      .chunk_by(|a, b| field_key(a, "files_id") == field_key(b, "files_id"))
+    {
+      let mut content = Vec::new();
+      let mut offsets = Vec::new();
+      for chunk in file_chunks {
+        let data = chunk
+          .get_binary("data")
+          .map_err(|err| format!("Invalid chunk in {}: {}", dump.namespace(), err))?;
+        offsets.push((content.len() as i64, u8::from(data.subtype) as i32));
+        content.extend_from_slice(data.bytes);
+      }
+
+      let object_name = object_name(&content, encryption_key);
+      let content = match encryption_key {
+        Some(key) => crypto::encrypt(key, &content)?,
+        None => content,
+      };
+      if datastores::put_shared_object(datastore, &object_name, &content)? {
+        stored += 1;
+      }
+
+      for (chunk, (offset, subtype)) in file_chunks.iter().zip(offsets) {
+        let mut chunk = to_document(chunk)?;
+        chunk.remove("data");
+        chunk.insert(OBJECT_FIELD, object_name.as_str());
+        chunk.insert(OFFSET_FIELD, offset);
+        chunk.insert(SUBTYPE_FIELD, subtype);
+        documents.push(RawDocumentBuf::try_from(&chunk).map_err(|err| err.to_string())?);
+      }
+    }
+
+    dump.documents = documents;
+    manifest.gridfs_objects = true;
+  }
+
+  Ok(stored)
+}
+
+/// Puts back the file contents of chunks stored as their own objects, checking them against
+/// their hash.
+pub fn load_files(
+  object: &mut BackupObject,
+  datastore: &dyn Datastore,
+  encryption_key: Option<&str>,
+) -> Result<(), String> {
+  let mut contents: HashMap<String, Vec<u8>> = HashMap::new();
+
+  for (manifest, dump) in object
+    .manifest
+    .collections
+    .iter_mut()
+    .zip(object.collections.iter_mut())
+  {
+    if !manifest.gridfs_objects {
+      continue;
+    }
+
+    let chunks: Vec<Document> = dump
+      .documents
+      .iter()
+      .map(to_document)
+      .collect::<Result<_, String>>()?;
+    let reference = |chunk: &Document| {
+      Some((
+        chunk.get_str(OBJECT_FIELD).ok()?.to_string(),
+        chunk.get(OFFSET_FIELD).and_then(Bson::as_i64)?,
+      ))
+    };
+
+    let mut documents = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.iter().enumerate() {
+      let (object_name, offset) =
+        reference(chunk).ok_or(format!("Invalid chunk in {}", dump.namespace()))?;
+
+      if !contents.contains_key(&object_name) {
+        let content = datastore.get_object(object_name.clone())?;
+        let content = match encryption_key {
+          Some(key) => crypto::decrypt(key, &content)?,
+          None => content,
+        };
+        if self::object_name(&content, encryption_key) != object_name {
+          return Err(format!("GridFS object {object_name} is corrupted"));
+        }
+        contents.insert(object_name.clone(), content);
+      }
+
+      // A chunk ends where the next chunk of the same file starts, files with the same content
+      // sharing their object
+      let content = &contents[&object_name];
+      let end = match chunks.get(i + 1) {
+        Some(next) if next.get("files_id") == chunk.get("files_id") => {
+          reference(next)
+            .ok_or(format!("Invalid chunk in {}", dump.namespace()))?
+            .1
+        }
+        _ => content.len() as i64,
+      };
+      let data = content
+        .get(offset as usize..end as usize)
+        .ok_or(format!("Invalid chunk range in {}", dump.namespace()))?;
+
+      let mut chunk = chunk.clone();
+      chunk.remove(OBJECT_FIELD);
+      chunk.remove(OFFSET_FIELD);
+      let subtype = match chunk.remove(SUBTYPE_FIELD) {
+        Some(Bson::Int32(subtype)) => BinarySubtype::from(subtype as u8),
+        _ => BinarySubtype::Generic,
+      };
+      chunk.insert(
+        "data",
+        Binary {
+          subtype,
+          bytes: data.to_vec(),
+        },
+      );
+      documents.push(RawDocumentBuf::try_from(&chunk).map_err(|err| err.to_string())?);
+    }
+
+    dump.documents = documents;
+    manifest.gridfs_objects = false;
+  }
+
+  Ok(())
+}
+
+/// Returns the name of the references object of a backup object.
+pub fn references_name(object_name: &str) -> String {
+  format!("{object_name}{REFERENCES_EXTENSION}")
+}
+
+/// Stores the GridFS objects a backup object refers to, if any, under its references object.
+/// Must be stored before the backup object, so that it never goes without them.
+pub fn store_references(
+  manifest: &BackupManifest,
+  source: &dyn CollectionSource,
+  datastore: &dyn Datastore,
+  object_name: &str,
+) -> Result<(), String> {
+  let mut references = BTreeSet::new();
+  for (i, collection) in manifest.collections.iter().enumerate() {
+    if !collection.gridfs_objects {
+      continue;
+    }
+    source.read_collection(i, &mut |chunks| {
+      for chunk in chunks {
+        if let Ok(object_name) = chunk.get_str(OBJECT_FIELD) {
+          references.insert(object_name.to_string());
+        }
+      }
+      Ok(())
+    })?;
+  }
+  if references.is_empty() {
+    return Ok(());
+  }
+
+  let content = serde_json::to_vec(&references)
+    .map_err(|err| format!("Cannot serialize GridFS references: {}", err))?;
+  datastore.put_object(&references_name(object_name), &content)
+}
+
+/// Deletes the GridFS objects no references object of the datastore refers to, returning their
+/// number. Fails while backups are being stored.
+pub fn collect_garbage(datastore: &dyn Datastore) -> Result<usize, String> {
+  let mut lock = GcLock::acquire(datastore)?;
+  let objects = datastore.list_objects()?;
+
+  let mut referenced = HashSet::new();
+  for object in objects
+    .iter()
+    .filter(|object| object.ends_with(REFERENCES_EXTENSION))
+  {
+    let content = datastore.get_object(object.to_string())?;
+    let references: Vec<String> = serde_json::from_slice(&content)
+      .map_err(|err| format!("Invalid GridFS references {}: {}", object, err))?;
+    referenced.extend(references);
+    lock.refresh()?;
+  }
+
+  let mut deleted = 0;
+  for object in objects
+    .iter()
+    .filter(|object| object.starts_with(OBJECT_PREFIX) && !referenced.contains(*object))
+  {
+    datastore.delete_object(object)?;
+    deleted += 1;
+    lock.refresh()?;
+  }
+  Ok(deleted)
+}
+
+/// Names file contents by their hash, an HMAC when encrypted so that names do not tell which
+/// backups hold the same files.
+fn object_name(content: &[u8], encryption_key: Option<&str>) -> String {
+  let hash: String = match encryption_key {
+    Some(key) => crypto::hmac(key, content),
+    None => Sha256::digest(content)
+      .iter()
+      .map(|byte| format!("{byte:02x}"))
+      .collect(),
+  };
+  format!("{OBJECT_PREFIX}{hash}")
+}
+
+fn to_document(document: &RawDocumentBuf) -> Result<Document, String> {
+  Document::try_from(document.as_ref()).map_err(|err| err.to_string())
+}
+
+/// A field value usable as a map key, as relaxed Extended JSON so that numbers of different types
+/// match like they do in queries.
+fn field_key(document: &RawDocumentBuf, field: &str) -> Option<String> {
+  let value = Bson::try_from(document.get(field).ok()??).ok()?;
+  Some(value.into_relaxed_extjson().to_string())
+}
+
+fn integer(document: &RawDocumentBuf, field: &str) -> Option<i64> {
+  match Bson::try_from(document.get(field).ok()??).ok()? {
+    Bson::Int32(value) => Some(value as i64),
+    Bson::Int64(value) => Some(value),
+    Bson::Double(value) => Some(value as i64),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{Binary, RawDocumentBuf, rawdoc, spec::BinarySubtype};
+
+  use crate::{
+    backups::{
+      BackupManifest, BackupObject, CollectionDump, CollectionManifest,
+      gridfs::{
+        bucket_name, check_bucket, collect_garbage, load_files, sort_chunks, store_files,
+        store_references,
+      },
+    },
+    datastores::{Datastore, FilesystemDatastore},
+    tests::{clean_test_dir, get_test_dir_path},
+    utils::crypto::generate_key,
+  };
+
+  fn binary(bytes: &[u8]) -> Binary {
+    Binary {
+      subtype: BinarySubtype::Generic,
+      bytes: bytes.to_vec(),
+    }
+  }
+
+  fn chunk(files_id: i32, n: i32, data: &[u8]) -> RawDocumentBuf {
+    rawdoc! { "_id": files_id * 100 + n, "files_id": files_id, "n": n, "data": binary(data) }
+  }
+
+  fn files() -> Vec<RawDocumentBuf> {
+    vec![
+      rawdoc! { "_id": 1, "length": 5_i64, "chunkSize": 2, "filename": "a.txt" },
+      rawdoc! { "_id": 2, "length": 0_i64, "chunkSize": 2, "filename": "empty.txt" },
+      rawdoc! { "_id": 3, "length": 2_i64, "chunkSize": 2, "filename": "b.txt" },
+    ]
+  }
+
+  #[test]
+  fn gridfs_bucket_name() {
+    let collections = vec![
+      "fs.files".to_string(),
+      "fs.chunks".to_string(),
+      "photos.files".to_string(),
+    ];
+
+    assert_eq!(bucket_name("fs.files", &collections), Some("fs"));
+    assert_eq!(bucket_name("fs.chunks", &collections), Some("fs"));
+    assert_eq!(bucket_name("photos.files", &collections), None);
+    assert_eq!(bucket_name("users", &collections), None);
+  }
+
+  #[test]
+  fn gridfs_check_bucket() {
+    let chunks = vec![
+      chunk(1, 0, b"ab"),
+      chunk(1, 1, b"cd"),
+      chunk(1, 2, b"e"),
+      chunk(3, 0, b"fg"),
+    ];
+    assert!(check_bucket(&files(), &chunks).is_empty());
+
+    let chunks = vec![
+      chunk(1, 0, b"ab"),
+      chunk(1, 2, b"e"),
+      chunk(3, 0, b"f"),
+      chunk(4, 0, b"hi"),
+    ];
+    let problems = check_bucket(&files(), &chunks);
+    assert_eq!(problems.len(), 3, "{problems:?}");
+    assert!(problems[0].starts_with("File 1 has chunks"));
+    assert_eq!(problems[1], "Chunk 0 of file 3 holds 1 bytes instead of 2");
+    assert!(problems[2].starts_with("Chunks of unknown file"));
+  }
+
+  #[test]
+  fn gridfs_sort_chunks() {
+    let mut chunks = vec![
+      chunk(4, 0, b"hi"),
+      chunk(3, 0, b"fg"),
+      chunk(1, 2, b"e"),
+      chunk(1, 0, b"ab"),
+      chunk(1, 1, b"cd"),
+    ];
+    sort_chunks(&files(), &mut chunks);
+
+    let order: Vec<i32> = chunks
+      .iter()
+      .map(|chunk| chunk.get_i32("_id").unwrap())
+      .collect();
+    assert_eq!(order, vec![100, 101, 102, 300, 400]);
+  }
+
+  #[test]
+  fn gridfs_store_load_files() {
+    let test_dir_path = get_test_dir_path("gridfs_store_load_files");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    let legacy_data = Binary {
+      subtype: BinarySubtype::BinaryOld,
+      bytes: b"fg".to_vec(),
+    };
+    let chunks = vec![
+      chunk(1, 0, b"ab"),
+      chunk(1, 1, b"cd"),
+      chunk(1, 2, b"e"),
+      rawdoc! { "_id": 300, "files_id": 3, "n": 0, "data": legacy_data },
+    ];
+    let sample_object = || {
+      let mut manifest = BackupManifest::new("cool", "Cool Backup");
+      let mut collections = Vec::new();
+      for (name, documents) in [("fs.files", files()), ("fs.chunks", chunks.clone())] {
+        manifest.collections.push(CollectionManifest {
+          database: "app".to_string(),
+          name: name.to_string(),
+          document_count: documents.len() as u64,
+          ..Default::default()
+        });
+        collections.push(CollectionDump {
+          database: "app".to_string(),
+          name: name.to_string(),
+          documents,
+        });
+      }
+      BackupObject {
+        manifest,
+        collections,
+      }
+    };
+
+    let mut object = sample_object();
+    assert_eq!(store_files(&mut object, &datastore, None).unwrap(), 2);
+    assert!(object.manifest.collections[1].gridfs_objects);
+    assert!(
+      object.collections[1].documents[0]
+        .get("data")
+        .unwrap()
+        .is_none()
+    );
+    assert_eq!(
+      store_files(&mut sample_object(), &datastore, None).unwrap(),
+      0
+    );
+
+    // Encrypted files are named by an HMAC, giving other names for the same files
+    let key = generate_key();
+    let mut encrypted = sample_object();
+    assert_eq!(
+      store_files(&mut encrypted, &datastore, Some(&key)).unwrap(),
+      2
+    );
+    load_files(&mut encrypted, &datastore, Some(&key)).unwrap();
+    assert_eq!(encrypted.collections[1].documents, chunks);
+
+    store_references(
+      &object.manifest,
+      &object.collections,
+      &datastore,
+      "backup_cool_1.json",
+    )
+    .unwrap();
+    assert_eq!(collect_garbage(&datastore).unwrap(), 2);
+    load_files(&mut object, &datastore, None).unwrap();
+    assert!(!object.manifest.collections[1].gridfs_objects);
+    assert_eq!(object.collections[1].documents, chunks);
+
+    datastore
+      .delete_object("backup_cool_1.json.gridfs")
+      .unwrap();
+    assert_eq!(collect_garbage(&datastore).unwrap(), 2);
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn gridfs_identical_files() {
+    let test_dir_path = get_test_dir_path("gridfs_identical_files");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    // Files with the same content follow each other, sharing one object
+    let files = vec![
+      rawdoc! { "_id": 1, "length": 3_i64, "chunkSize": 2, "filename": "a.txt" },
+      rawdoc! { "_id": 2, "length": 3_i64, "chunkSize": 2, "filename": "copy.txt" },
+    ];
+    let chunks = vec![
+      chunk(1, 0, b"ab"),
+      chunk(1, 1, b"c"),
+      chunk(2, 0, b"ab"),
+      chunk(2, 1, b"c"),
+    ];
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    let mut collections = Vec::new();
+    for (name, documents) in [("fs.files", files), ("fs.chunks", chunks.clone())] {
+      manifest.collections.push(CollectionManifest {
+        database: "app".to_string(),
+        name: name.to_string(),
+        ..Default::default()
+      });
+      collections.push(CollectionDump {
+        database: "app".to_string(),
+        name: name.to_string(),
+        documents,
+      });
+    }
+    let mut object = BackupObject {
+      manifest,
+      collections,
+    };
+
+    assert_eq!(store_files(&mut object, &datastore, None).unwrap(), 1);
+    load_files(&mut object, &datastore, None).unwrap();
+    assert_eq!(object.collections[1].documents, chunks);
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn gridfs_load_corrupted_file() {
+    let test_dir_path = get_test_dir_path("gridfs_load_corrupted_file");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest.collections.push(CollectionManifest {
+      database: "app".to_string(),
+      name: "fs.chunks".to_string(),
+      gridfs_objects: true,
+      ..Default::default()
+    });
+    let _ = datastore.put_object("gridfs_0000", b"ab");
+    let mut object = BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: "app".to_string(),
+        name: "fs.chunks".to_string(),
+        documents: vec![
+          rawdoc! { "files_id": 1, "n": 0, "mbmObject": "gridfs_0000", "mbmOffset": 0_i64 },
+        ],
+      }],
+    };
+
+    assert!(load_files(&mut object, &datastore, None).is_err());
+
+    clean_test_dir(test_dir_path);
+  }
+}
@@ -0,0 +1,296 @@
+use std::{
+  fs::{self, File},
+  io::{Cursor, Read},
+  path::Path,
+  time::SystemTime,
+};
+
+use bson::RawDocumentBuf;
+use chrono::{DateTime, Local};
+
+use crate::{
+  backups::{
+    BackupKind, BackupManifest, BackupObject, CollectionDump, CollectionManifest, ObjectName,
+    formats::archive,
+  },
+  datastores,
+  utils::{compression, config::Backup, logger::Logger},
+};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Imports a `mongodump` output directory or `--archive` file into the datastore of a backup.
+pub struct DumpImporter<'a> {
+  name: &'a str,
+  backup: &'a Backup,
+}
+
+impl<'a> DumpImporter<'a> {
+  pub fn new(name: &'a str, backup: &'a Backup) -> Self {
+    Self { name, backup }
+  }
+
+  /// Stores the dump found at `path` and returns the created object name. The object is dated
+  /// with `timestamp`, or with the dump modification time when not provided.
+  pub fn import(&self, path: &Path, timestamp: Option<i64>) -> Result<String, String> {
+    let mut object = if path.is_dir() {
+      Self::read_directory(path)?
+    } else {
+      Self::read_archive(path)?
+    };
+
+    let created_at = match timestamp {
+      Some(timestamp) => DateTime::from_timestamp(timestamp, 0)
+        .ok_or(format!("Invalid timestamp {timestamp}"))?
+        .with_timezone(&Local),
+      None => fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map(DateTime::<Local>::from)
+        .unwrap_or(DateTime::from(SystemTime::now())),
+    };
+
+    object.manifest.backup_name = self.name.to_string();
+    object.manifest.display_name = self.backup.display_name.clone();
+    object.manifest.created_at = created_at.to_rfc3339();
+
+    let object_name = ObjectName {
+      directory: String::new(),
+      backup_name: self.name.to_string(),
+      timestamp: created_at.timestamp(),
+      kind: BackupKind::Full,
+      format: self.backup.format,
+      compression: self.backup.compression,
+      dedup: false,
+    }
+    .to_string();
+    let content = object.encode(
+      self.backup.format,
+      self.backup.json_mode,
+      self.backup.compression,
+      self.backup.encryption_key.as_deref(),
+    )?;
+
+    datastores::from_config(&self.backup.datastore)?.put_object(&object_name, &content)?;
+
+    Logger::highlight(&format!(
+      "Imported {} documents from {} collections of {} into {}",
+      object.manifest.document_count(),
+      object.manifest.collections.len(),
+      path.display(),
+      object_name
+    ));
+
+    Ok(object_name)
+  }
+
+  fn read_archive(path: &Path) -> Result<BackupObject, String> {
+    let content = Self::read_file(path)?;
+    archive::decode(&content)
+  }
+
+  /// Reads a dump directory, either the `mongodump` output directory holding one directory per
+  /// database, or a single database directory.
+  fn read_directory(path: &Path) -> Result<BackupObject, String> {
+    let mut object = BackupObject {
+      manifest: BackupManifest::new("", ""),
+      collections: Vec::new(),
+    };
+
+    let mut entries: Vec<_> = fs::read_dir(path)
+      .map_err(|err| format!("Cannot read directory {}: {}", path.display(), err))?
+      .filter_map(Result::ok)
+      .map(|entry| entry.path())
+      .collect();
+    entries.sort();
+
+    if entries
+      .iter()
+      .any(|entry| Self::collection_name(entry).is_some())
+    {
+      Self::read_database(path, &mut object)?;
+    } else {
+      for entry in entries.iter().filter(|entry| entry.is_dir()) {
+        Self::read_database(entry, &mut object)?;
+      }
+    }
+
+    if object.collections.is_empty() {
+      return Err(format!("No collection found in {}", path.display()));
+    }
+
+    Ok(object)
+  }
+
+  fn read_database(path: &Path, object: &mut BackupObject) -> Result<(), String> {
+    let database = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .ok_or(format!("Invalid database directory {}", path.display()))?
+      .to_string();
+
+    let mut files: Vec<_> = fs::read_dir(path)
+      .map_err(|err| format!("Cannot read directory {}: {}", path.display(), err))?
+      .filter_map(Result::ok)
+      .map(|entry| entry.path())
+      .collect();
+    files.sort();
+
+    for file in files {
+      let Some((name, extension)) = Self::collection_name(&file) else {
+        continue;
+      };
+      if name.starts_with("system.") {
+        continue;
+      }
+
+      let documents = Self::read_bson_file(&file)?;
+      let metadata = path.join(format!("{name}.metadata.json{extension}"));
+      let (indexes, options) = if metadata.exists() {
+        let metadata = String::from_utf8(Self::read_file(&metadata)?)
+          .map_err(|err| format!("Invalid metadata file {}: {}", metadata.display(), err))?;
+        archive::parse_metadata(&metadata)?
+      } else {
+        Default::default()
+      };
+
+      object.manifest.collections.push(CollectionManifest {
+        database: database.clone(),
+        name: name.clone(),
+        document_count: documents.len() as u64,
+        indexes,
+        options,
+        ..Default::default()
+      });
+      object.collections.push(CollectionDump {
+        database: database.clone(),
+        name,
+        documents,
+      });
+    }
+
+    Ok(())
+  }
+
+  /// Returns the collection name and compression extension of a `<collection>.bson[.gz]` file.
+  fn collection_name(path: &Path) -> Option<(String, &'static str)> {
+    let file_name = path.file_name()?.to_str()?;
+
+    if let Some(name) = file_name.strip_suffix(".bson.gz") {
+      Some((name.to_string(), ".gz"))
+    } else {
+      file_name
+        .strip_suffix(".bson")
+        .map(|name| (name.to_string(), ""))
+    }
+  }
+
+  fn read_bson_file(path: &Path) -> Result<Vec<RawDocumentBuf>, String> {
+    let content = Self::read_file(path)?;
+    let len = content.len() as u64;
+    let mut cursor = Cursor::new(content);
+
+    let mut documents = Vec::new();
+    while cursor.position() < len {
+      documents.push(
+        RawDocumentBuf::from_reader(&mut cursor)
+          .map_err(|err| format!("Invalid document in {}: {}", path.display(), err))?,
+      );
+    }
+
+    Ok(documents)
+  }
+
+  /// Reads a file, transparently decompressing it when gzipped.
+  fn read_file(path: &Path) -> Result<Vec<u8>, String> {
+    let mut content = Vec::new();
+    File::open(path)
+      .and_then(|mut file| file.read_to_end(&mut content))
+      .map_err(|err| format!("Cannot read file {}: {}", path.display(), err))?;
+
+    if content.starts_with(&GZIP_MAGIC) {
+      compression::gunzip(&content)
+    } else {
+      Ok(content)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    fs::{create_dir_all, write},
+    path::Path,
+  };
+
+  use bson::{Bson, doc};
+
+  use crate::{
+    backups::import::DumpImporter,
+    tests::{clean_test_dir, get_test_dir_path},
+  };
+
+  fn write_dump(path: &str) {
+    let database = format!("{path}/database");
+    let _ = create_dir_all(&database);
+
+    let mut users = Vec::new();
+    for name in ["Nolhan", "ValDesign"] {
+      doc! { "name": name }.to_writer(&mut users).unwrap();
+    }
+    let _ = write(format!("{database}/users.bson"), users);
+    let metadata = Bson::Document(doc! {
+      "indexes": [
+        { "v": 2, "key": { "_id": 1 }, "name": "_id_" },
+        { "v": 2, "key": { "name": 1 }, "name": "name_1", "unique": true },
+      ],
+      "collectionName": "users",
+      "options": { "collation": { "locale": "fr" } },
+    });
+    let _ = write(
+      format!("{database}/users.metadata.json"),
+      metadata.into_canonical_extjson().to_string(),
+    );
+    let _ = write(format!("{database}/empty.bson"), b"");
+  }
+
+  #[test]
+  fn import_read_dump_directory() {
+    let test_dir_path = get_test_dir_path("import_read_dump_directory");
+    clean_test_dir(test_dir_path.clone());
+    write_dump(&test_dir_path);
+
+    for path in [test_dir_path.clone(), format!("{test_dir_path}/database")] {
+      let object = DumpImporter::read_directory(Path::new(&path)).unwrap();
+      let manifest = &object.manifest.collections;
+
+      assert_eq!(manifest.len(), 2);
+      assert_eq!(manifest[0].namespace(), "database.empty");
+      assert_eq!(manifest[0].document_count, 0);
+      assert_eq!(manifest[1].namespace(), "database.users");
+      assert_eq!(manifest[1].document_count, 2);
+      assert_eq!(manifest[1].index_names(), vec!["_id_", "name_1"]);
+      assert_eq!(manifest[1].indexes[1].options, doc! { "unique": true });
+      assert_eq!(
+        manifest[1].options,
+        doc! { "collation": { "locale": "fr" } }
+      );
+      assert_eq!(
+        object.collections[1].documents[1].get_str("name").unwrap(),
+        "ValDesign"
+      );
+    }
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn import_read_empty_directory() {
+    let test_dir_path = get_test_dir_path("import_read_empty_directory");
+    clean_test_dir(test_dir_path.clone());
+    let _ = create_dir_all(&test_dir_path);
+
+    assert!(DumpImporter::read_directory(Path::new(&test_dir_path)).is_err());
+
+    clean_test_dir(test_dir_path);
+  }
+}
@@ -0,0 +1,315 @@
+use bson::{Document, Timestamp, doc};
+use serde::{Deserialize, Serialize};
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// Fields of an index specification that are not index options.
+const INDEX_SPEC_FIELDS: [&str; 4] = ["v", "key", "name", "ns"];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IndexManifest {
+  pub name: String,
+  pub keys: Document,
+  /// Options of the index, like `unique`, `expireAfterSeconds` or `collation`.
+  #[serde(default, skip_serializing_if = "Document::is_empty")]
+  pub options: Document,
+}
+
+impl IndexManifest {
+  /// Reads an index specification, as returned by `listIndexes`.
+  pub fn from_spec(spec: &Document) -> Option<Self> {
+    Some(Self {
+      name: spec.get_str("name").ok()?.to_string(),
+      keys: spec.get_document("key").ok()?.clone(),
+      options: spec
+        .iter()
+        .filter(|(field, _)| !INDEX_SPEC_FIELDS.contains(&field.as_str()))
+        .map(|(field, value)| (field.clone(), value.clone()))
+        .collect(),
+    })
+  }
+
+  /// The index specification, as given to `createIndexes`.
+  pub fn spec(&self) -> Document {
+    let mut spec = doc! { "key": self.keys.clone(), "name": &self.name };
+    spec.extend(self.options.clone());
+    spec
+  }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionManifest {
+  pub database: String,
+  pub name: String,
+  pub document_count: u64,
+  pub indexes: Vec<IndexManifest>,
+  /// Options the collection was created with, as returned by `listCollections`, like `capped`,
+  /// `collation` or `validator`.
+  #[serde(default, skip_serializing_if = "Document::is_empty")]
+  pub options: Document,
+  /// Shard key of the collection, when dumped from a sharded cluster.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sharding: Option<ShardingManifest>,
+  /// Whether the `data` of these GridFS chunks is stored in separate `gridfs_<hash>` objects.
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  pub gridfs_objects: bool,
+  /// Query the documents were selected with, when only part of the collection is backed up.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub filter: Option<Document>,
+  /// Fields the documents were read with, when some are left out of the backup.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub projection: Option<Document>,
+}
+
+impl CollectionManifest {
+  pub fn namespace(&self) -> String {
+    format!("{}.{}", self.database, self.name)
+  }
+
+  pub fn is_timeseries(&self) -> bool {
+    self.options.contains_key("timeseries")
+  }
+
+  pub fn is_capped(&self) -> bool {
+    self.options.get_bool("capped").unwrap_or(false)
+  }
+
+  /// Whether the backup leaves out documents or fields of the collection.
+  pub fn is_partial(&self) -> bool {
+    self.filter.is_some() || self.projection.is_some()
+  }
+
+  pub fn index_names(&self) -> Vec<String> {
+    let mut names: Vec<String> = self.indexes.iter().map(|i| i.name.clone()).collect();
+    names.sort();
+    names
+  }
+}
+
+/// Fields of a `config.collections` entry recorded along the shard key.
+const SHARDING_FIELDS: [&str; 3] = ["defaultCollation", "noBalance", "maxChunkSizeBytes"];
+
+/// How a collection of a sharded cluster is sharded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShardingManifest {
+  pub key: Document,
+  #[serde(default)]
+  pub unique: bool,
+  /// Other fields of the `config.collections` entry affecting chunk distribution, like
+  /// `noBalance` or `maxChunkSizeBytes`.
+  #[serde(default, skip_serializing_if = "Document::is_empty")]
+  pub options: Document,
+}
+
+impl ShardingManifest {
+  /// Reads a `config.collections` entry.
+  pub fn from_config(entry: &Document) -> Option<Self> {
+    Some(Self {
+      key: entry.get_document("key").ok()?.clone(),
+      unique: entry.get_bool("unique").unwrap_or(false),
+      options: SHARDING_FIELDS
+        .iter()
+        .filter_map(|field| Some((field.to_string(), entry.get(*field)?.clone())))
+        .collect(),
+    })
+  }
+}
+
+/// A view, recreated from its definition rather than from dumped documents.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ViewManifest {
+  pub database: String,
+  pub name: String,
+  /// Definition of the view, as returned by `listCollections`: `viewOn`, `pipeline` and
+  /// `collation`.
+  pub options: Document,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupKind {
+  #[default]
+  Full,
+  /// An oplog segment to replay on top of its base full backup.
+  Incremental,
+  /// A segment of change events recorded from a change stream.
+  Changes,
+  /// A full backup with its configured fields masked, safe to hand out of production.
+  Sanitized,
+}
+
+/// A position in the oplog, stored as the `ts` field of its entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OplogPosition {
+  pub time: u32,
+  pub increment: u32,
+}
+
+impl From<Timestamp> for OplogPosition {
+  fn from(timestamp: Timestamp) -> Self {
+    Self {
+      time: timestamp.time,
+      increment: timestamp.increment,
+    }
+  }
+}
+
+impl From<OplogPosition> for Timestamp {
+  fn from(position: OplogPosition) -> Self {
+    Self {
+      time: position.time,
+      increment: position.increment,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupManifest {
+  pub version: u32,
+  pub backup_name: String,
+  pub display_name: String,
+  pub created_at: String,
+  #[serde(default)]
+  pub kind: BackupKind,
+  /// Databases the backup covers, including those without any collection.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub databases: Vec<String>,
+  /// Object name of the full backup an incremental backup is chained to.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub base: Option<String>,
+  /// Oplog position the backup starts after, for incremental backups and change segments.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub oplog_start: Option<OplogPosition>,
+  /// Last oplog position covered by the backup.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub oplog_end: Option<OplogPosition>,
+  /// Cluster time of the snapshot every collection was read at, when the dump is consistent.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub cluster_time: Option<OplogPosition>,
+  pub collections: Vec<CollectionManifest>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub views: Vec<ViewManifest>,
+  /// Users of the backed up databases, as stored in `admin.system.users`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub users: Vec<Document>,
+  /// Roles of the backed up databases, as stored in `admin.system.roles`.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub roles: Vec<Document>,
+}
+
+impl BackupManifest {
+  pub fn new(backup_name: &str, display_name: &str) -> Self {
+    Self {
+      version: MANIFEST_VERSION,
+      backup_name: backup_name.to_string(),
+      display_name: display_name.to_string(),
+      created_at: chrono::Local::now().to_rfc3339(),
+      kind: BackupKind::Full,
+      databases: Vec::new(),
+      base: None,
+      oplog_start: None,
+      oplog_end: None,
+      cluster_time: None,
+      collections: Vec::new(),
+      views: Vec::new(),
+      users: Vec::new(),
+      roles: Vec::new(),
+    }
+  }
+
+  /// Databases the backup covers, falling back to those of its collections for older manifests.
+  pub fn database_names(&self) -> Vec<String> {
+    if !self.databases.is_empty() {
+      return self.databases.clone();
+    }
+
+    let mut databases: Vec<String> = self
+      .collections
+      .iter()
+      .map(|c| c.database.clone())
+      .collect();
+    databases.sort();
+    databases.dedup();
+    databases
+  }
+
+  pub fn document_count(&self) -> u64 {
+    self.collections.iter().map(|c| c.document_count).sum()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::doc;
+
+  use crate::backups::{BackupKind, BackupManifest, IndexManifest, ShardingManifest};
+
+  #[test]
+  fn manifest_without_oplog_fields() {
+    let manifest: BackupManifest = serde_json::from_str(
+      r#"{"version":1,"backup_name":"cool","display_name":"Cool","created_at":"","collections":[]}"#,
+    )
+    .unwrap();
+
+    assert_eq!(manifest.kind, BackupKind::Full);
+    assert_eq!(manifest.oplog_end, None);
+    assert_eq!(manifest.cluster_time, None);
+    let json = serde_json::to_string(&manifest).unwrap();
+    assert!(!json.contains("oplog_start"));
+    assert!(!json.contains("cluster_time"));
+  }
+
+  #[test]
+  fn manifest_index_spec() {
+    let spec = doc! {
+      "v": 2,
+      "key": { "createdAt": 1 },
+      "name": "createdAt_1",
+      "expireAfterSeconds": 3600,
+      "partialFilterExpression": { "archived": false },
+    };
+
+    let index = IndexManifest::from_spec(&spec).unwrap();
+    assert_eq!(index.name, "createdAt_1");
+    assert_eq!(index.keys, doc! { "createdAt": 1 });
+    assert_eq!(
+      index.options,
+      doc! { "expireAfterSeconds": 3600, "partialFilterExpression": { "archived": false } }
+    );
+    assert_eq!(
+      index.spec(),
+      doc! {
+        "key": { "createdAt": 1 },
+        "name": "createdAt_1",
+        "expireAfterSeconds": 3600,
+        "partialFilterExpression": { "archived": false },
+      }
+    );
+    assert_eq!(IndexManifest::from_spec(&doc! { "v": 2 }), None);
+  }
+
+  #[test]
+  fn manifest_sharding_from_config() {
+    let entry = doc! {
+      "_id": "app.events",
+      "lastmodEpoch": 1,
+      "key": { "tenant": 1, "_id": "hashed" },
+      "unique": false,
+      "noBalance": true,
+      "uuid": "d2f0",
+    };
+
+    assert_eq!(
+      ShardingManifest::from_config(&entry),
+      Some(ShardingManifest {
+        key: doc! { "tenant": 1, "_id": "hashed" },
+        unique: false,
+        options: doc! { "noBalance": true },
+      })
+    );
+    assert_eq!(
+      ShardingManifest::from_config(&doc! { "_id": "app.users" }),
+      None
+    );
+  }
+}
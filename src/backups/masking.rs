@@ -0,0 +1,268 @@
+use std::collections::HashMap;
+
+use crate::{
+  backups::{BackupKind, BackupManifest, BackupObject, CollectionDump, selection},
+  utils::{config::MaskAction, crypto},
+};
+use bson::{Bson, Document, RawDocumentBuf};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// Builds the sanitized variant of a backup batch by batch, masking the configured fields of
+/// every document as it is added, so that the documents of the backup are never held twice.
+/// Hashed values are keyed by `secret`.
+pub struct Sanitizer<'a> {
+  masking: &'a HashMap<String, HashMap<String, MaskAction>>,
+  secret: &'a str,
+  object: BackupObject,
+}
+
+impl<'a> Sanitizer<'a> {
+  pub fn new(
+    manifest: &BackupManifest,
+    masking: &'a HashMap<String, HashMap<String, MaskAction>>,
+    secret: &'a str,
+  ) -> Self {
+    let mut manifest = manifest.clone();
+    manifest.kind = BackupKind::Sanitized;
+    // Users hold their credentials, the variant is meant to be shared more widely than the backup
+    manifest.users.clear();
+    manifest.roles.clear();
+    let collections = manifest
+      .collections
+      .iter()
+      .map(|collection| CollectionDump {
+        database: collection.database.clone(),
+        name: collection.name.clone(),
+        documents: Vec::new(),
+      })
+      .collect();
+
+    Self {
+      masking,
+      secret,
+      object: BackupObject {
+        manifest,
+        collections,
+      },
+    }
+  }
+
+  /// Masks documents of the collection at `collection` in the manifest, and adds them to it.
+  pub fn add(&mut self, collection: usize, documents: Vec<RawDocumentBuf>) -> Result<(), String> {
+    let dump = &mut self.object.collections[collection];
+    let Some(rules) = selection::collection_setting(self.masking, &dump.database, &dump.name)
+    else {
+      dump.documents.extend(documents);
+      return Ok(());
+    };
+
+    for document in documents {
+      let document = mask_document(&document, rules, self.secret)
+        .map_err(|err| format!("Cannot mask a document of {}: {}", dump.namespace(), err))?;
+      dump.documents.push(document);
+    }
+    Ok(())
+  }
+
+  pub fn finish(self) -> BackupObject {
+    self.object
+  }
+}
+
+/// Masks the fields of a document at the given dotted paths. Paths go through arrays, masking
+/// every element.
+pub fn mask_document(
+  document: &RawDocumentBuf,
+  rules: &HashMap<String, MaskAction>,
+  secret: &str,
+) -> Result<RawDocumentBuf, String> {
+  let mut document = Document::try_from(document.as_ref()).map_err(|err| err.to_string())?;
+  for (path, action) in rules {
+    let path: Vec<&str> = path.split('.').collect();
+    mask_path(&mut document, &path, *action, secret);
+  }
+  RawDocumentBuf::try_from(&document).map_err(|err| err.to_string())
+}
+
+fn mask_path(document: &mut Document, path: &[&str], action: MaskAction, secret: &str) {
+  let Some((field, rest)) = path.split_first() else {
+    return;
+  };
+  if let Some(value) = document.get_mut(*field) {
+    mask_value(value, rest, action, secret);
+  }
+}
+
+fn mask_value(value: &mut Bson, path: &[&str], action: MaskAction, secret: &str) {
+  match value {
+    Bson::Array(values) => values
+      .iter_mut()
+      .for_each(|value| mask_value(value, path, action, secret)),
+    Bson::Document(document) if !path.is_empty() => mask_path(document, path, action, secret),
+    _ if path.is_empty() => *value = mask(value, action, secret),
+    _ => {}
+  }
+}
+
+/// Returns the masked value. Null values are kept, so masking does not make up missing data.
+pub fn mask(value: &Bson, action: MaskAction, secret: &str) -> Bson {
+  if *value == Bson::Null {
+    return Bson::Null;
+  }
+
+  match action {
+    MaskAction::Hash => Bson::String(hash(value, secret)),
+    MaskAction::Redact => Bson::String(REDACTED.to_string()),
+    MaskAction::FakeEmail => {
+      Bson::String(format!("user_{}@example.com", &hash(value, secret)[..16]))
+    }
+    MaskAction::Truncate(length) => match value {
+      Bson::String(text) => Bson::String(text.chars().take(length).collect()),
+      _ => value.clone(),
+    },
+    MaskAction::Null => Bson::Null,
+  }
+}
+
+fn hash(value: &Bson, secret: &str) -> String {
+  let content = match value {
+    Bson::String(text) => text.clone(),
+    _ => value.clone().into_canonical_extjson().to_string(),
+  };
+
+  crypto::hmac(secret, content.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use bson::{Bson, Document, doc, rawdoc};
+
+  use crate::{
+    backups::{
+      BackupKind, BackupManifest, CollectionManifest,
+      masking::{Sanitizer, mask, mask_document},
+    },
+    utils::config::MaskAction,
+  };
+
+  const SECRET: &str = "secret";
+
+  #[test]
+  fn masking_mask() {
+    let email = Bson::String("jane.doe@example.org".to_string());
+
+    assert_eq!(
+      mask(&Bson::String("abc".to_string()), MaskAction::Hash, SECRET),
+      Bson::String("9946dad4e00e913fc8be8e5d3f7e110a4a9e832f83fb09c345285d78638d8a0e".to_string())
+    );
+    assert_eq!(
+      mask(&email, MaskAction::Hash, SECRET),
+      mask(
+        &Bson::String("jane.doe@example.org".to_string()),
+        MaskAction::Hash,
+        SECRET
+      )
+    );
+    assert_ne!(
+      mask(&email, MaskAction::Hash, SECRET),
+      mask(&Bson::Int32(1), MaskAction::Hash, SECRET)
+    );
+    // Without the secret, hashing guesses does not find the values back
+    assert_ne!(
+      mask(&email, MaskAction::Hash, SECRET),
+      mask(&email, MaskAction::Hash, "guess")
+    );
+    assert_eq!(
+      mask(&email, MaskAction::Redact, SECRET),
+      Bson::String("[REDACTED]".to_string())
+    );
+    assert!(
+      mask(&email, MaskAction::FakeEmail, SECRET)
+        .as_str()
+        .is_some_and(|fake| fake.starts_with("user_") && fake.ends_with("@example.com"))
+    );
+    assert_eq!(
+      mask(&email, MaskAction::Truncate(4), SECRET),
+      Bson::String("jane".to_string())
+    );
+    assert_eq!(
+      mask(&Bson::Int32(42), MaskAction::Truncate(1), SECRET),
+      Bson::Int32(42)
+    );
+    assert_eq!(mask(&email, MaskAction::Null, SECRET), Bson::Null);
+    assert_eq!(mask(&Bson::Null, MaskAction::Redact, SECRET), Bson::Null);
+  }
+
+  #[test]
+  fn masking_mask_document() {
+    let rules = HashMap::from([
+      ("email".to_string(), MaskAction::Redact),
+      ("profile.phone".to_string(), MaskAction::Null),
+      ("addresses.city".to_string(), MaskAction::Truncate(1)),
+      ("missing.field".to_string(), MaskAction::Redact),
+    ]);
+    let document = rawdoc! {
+      "_id": 1,
+      "email": "jane.doe@example.org",
+      "profile": { "phone": "+33 6 00 00 00 00", "age": 42 },
+      "addresses": [{ "city": "Paris" }, { "city": "Lyon" }],
+    };
+
+    let masked =
+      Document::try_from(mask_document(&document, &rules, SECRET).unwrap().as_ref()).unwrap();
+    assert_eq!(
+      masked,
+      doc! {
+        "_id": 1,
+        "email": "[REDACTED]",
+        "profile": { "phone": Bson::Null, "age": 42 },
+        "addresses": [{ "city": "P" }, { "city": "L" }],
+      }
+    );
+  }
+
+  #[test]
+  fn masking_sanitizer() {
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest
+      .users
+      .push(doc! { "user": "admin", "credentials": { "SCRAM-SHA-256": {} } });
+    manifest.roles.push(doc! { "role": "reader" });
+    for name in ["users", "products"] {
+      manifest.collections.push(CollectionManifest {
+        database: "app".to_string(),
+        name: name.to_string(),
+        ..Default::default()
+      });
+    }
+    let masking = HashMap::from([(
+      "app.users".to_string(),
+      HashMap::from([("email".to_string(), MaskAction::Redact)]),
+    )]);
+
+    let mut sanitizer = Sanitizer::new(&manifest, &masking, SECRET);
+    let product = rawdoc! { "email": "shop@example.org" };
+    sanitizer
+      .add(0, vec![rawdoc! { "email": "jane.doe@example.org" }])
+      .unwrap();
+    sanitizer.add(1, vec![product.clone()]).unwrap();
+    sanitizer
+      .add(0, vec![rawdoc! { "email": "john.doe@example.org" }])
+      .unwrap();
+
+    let sanitized = sanitizer.finish();
+    assert_eq!(sanitized.manifest.kind, BackupKind::Sanitized);
+    assert!(sanitized.manifest.users.is_empty() && sanitized.manifest.roles.is_empty());
+    assert_eq!(
+      sanitized.collections[0].documents,
+      vec![
+        rawdoc! { "email": "[REDACTED]" },
+        rawdoc! { "email": "[REDACTED]" }
+      ]
+    );
+    assert_eq!(sanitized.collections[1].documents, vec![product]);
+  }
+}
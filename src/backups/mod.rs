@@ -0,0 +1,31 @@
+pub mod catalog;
+pub use catalog::{BackupCatalog, ObjectName};
+pub mod checkpoint;
+pub use checkpoint::{Checkpoint, PartsSource};
+pub mod drill;
+pub use drill::RestoreDrill;
+pub mod engine;
+pub use engine::BackupEngine;
+pub mod formats;
+pub mod gridfs;
+pub mod import;
+pub use import::DumpImporter;
+pub mod manifest;
+pub use manifest::{
+  BackupKind, BackupManifest, CollectionManifest, IndexManifest, OplogPosition, ShardingManifest,
+  ViewManifest,
+};
+pub mod masking;
+pub mod oplog;
+pub use oplog::OplogState;
+pub mod object;
+pub use object::{BackupObject, CollectionDump, CollectionSource};
+pub mod parallel;
+pub mod restore;
+pub use restore::{RestoreEngine, RestoreOptions};
+pub mod scheduler;
+pub mod security;
+pub use scheduler::Scheduler;
+pub mod selection;
+pub mod stream;
+pub use stream::ChangeStreamRecorder;
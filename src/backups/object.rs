@@ -0,0 +1,228 @@
+use std::io::Write;
+
+use bson::RawDocumentBuf;
+
+use crate::{
+  backups::{BackupManifest, formats},
+  utils::{
+    compression,
+    config::{BackupCompression, BackupFormat, JsonMode},
+    crypto,
+  },
+};
+
+pub struct CollectionDump {
+  pub database: String,
+  pub name: String,
+  pub documents: Vec<RawDocumentBuf>,
+}
+
+impl CollectionDump {
+  pub fn namespace(&self) -> String {
+    format!("{}.{}", self.database, self.name)
+  }
+}
+
+/// The documents of the collections of a backup, handed over batch by batch, so that a backup is
+/// encoded without holding all of them.
+pub trait CollectionSource {
+  /// Number of documents of the collection at `index` in the manifest.
+  fn count(&self, index: usize) -> u64;
+
+  /// Hands the documents of the collection at `index` in the manifest over to `on_batch`, in
+  /// order.
+  fn read_collection(
+    &self,
+    index: usize,
+    on_batch: &mut dyn FnMut(&[RawDocumentBuf]) -> Result<(), String>,
+  ) -> Result<(), String>;
+}
+
+impl CollectionSource for Vec<CollectionDump> {
+  fn count(&self, index: usize) -> u64 {
+    self
+      .get(index)
+      .map_or(0, |dump| dump.documents.len() as u64)
+  }
+
+  fn read_collection(
+    &self,
+    index: usize,
+    on_batch: &mut dyn FnMut(&[RawDocumentBuf]) -> Result<(), String>,
+  ) -> Result<(), String> {
+    match self.get(index) {
+      Some(dump) => on_batch(&dump.documents),
+      None => Ok(()),
+    }
+  }
+}
+
+/// Serializes a backup in the given format as its documents are read from `source`, compressing
+/// it on the way, then encrypts it.
+pub fn encode_collections(
+  manifest: &BackupManifest,
+  source: &dyn CollectionSource,
+  format: BackupFormat,
+  json_mode: JsonMode,
+  compression: BackupCompression,
+  encryption_key: Option<&str>,
+) -> Result<Vec<u8>, String> {
+  let write = |content: &mut dyn Write| match format {
+    BackupFormat::Json => formats::json::write(manifest, source, json_mode, content),
+    BackupFormat::Archive => formats::archive::write(manifest, source, content),
+    BackupFormat::Bson => formats::bson_stream::write(manifest, source, content),
+  };
+  let content = match compression {
+    BackupCompression::None => {
+      let mut content = Vec::new();
+      write(&mut content)?;
+      content
+    }
+    BackupCompression::Gzip => compression::gzip_with(write)?,
+  };
+
+  match encryption_key {
+    Some(key) => crypto::encrypt(key, &content),
+    None => Ok(content),
+  }
+}
+
+/// A complete backup as stored in a datastore: its manifest and the dumped documents.
+pub struct BackupObject {
+  pub manifest: BackupManifest,
+  pub collections: Vec<CollectionDump>,
+}
+
+impl BackupObject {
+  /// Serializes the backup in the given format, then compresses and encrypts it.
+  pub fn encode(
+    &self,
+    format: BackupFormat,
+    json_mode: JsonMode,
+    compression: BackupCompression,
+    encryption_key: Option<&str>,
+  ) -> Result<Vec<u8>, String> {
+    encode_collections(
+      &self.manifest,
+      &self.collections,
+      format,
+      json_mode,
+      compression,
+      encryption_key,
+    )
+  }
+
+  pub fn decode(
+    content: &[u8],
+    format: BackupFormat,
+    compression: BackupCompression,
+    encryption_key: Option<&str>,
+  ) -> Result<Self, String> {
+    let content = match encryption_key {
+      Some(key) => crypto::decrypt(key, content)?,
+      None => content.to_vec(),
+    };
+
+    let content = match compression {
+      BackupCompression::None => content,
+      BackupCompression::Gzip => compression::gunzip(&content)?,
+    };
+
+    match format {
+      BackupFormat::Json => formats::json::decode(&content),
+      BackupFormat::Archive => formats::archive::decode(&content),
+      BackupFormat::Bson => formats::bson_stream::decode(&content),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{doc, oid::ObjectId, rawdoc};
+
+  use crate::{
+    backups::{BackupManifest, BackupObject, CollectionDump, CollectionManifest, IndexManifest},
+    utils::{
+      config::{BackupCompression, BackupFormat, JsonMode},
+      crypto::generate_key,
+    },
+  };
+
+  fn sample_object() -> BackupObject {
+    let mut manifest = BackupManifest::new("cool", "Cool Backup");
+    manifest.collections.push(CollectionManifest {
+      database: "database".to_string(),
+      name: "users".to_string(),
+      document_count: 2,
+      indexes: vec![IndexManifest {
+        name: "_id_".to_string(),
+        keys: doc! { "_id": 1 },
+        ..Default::default()
+      }],
+      ..Default::default()
+    });
+
+    BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: "database".to_string(),
+        name: "users".to_string(),
+        documents: vec![
+          rawdoc! { "_id": ObjectId::new(), "name": "Nolhan", "age": 21 },
+          rawdoc! { "_id": ObjectId::new(), "name": "ValDesign", "tags": ["admin"] },
+        ],
+      }],
+    }
+  }
+
+  #[test]
+  fn backup_object_roundtrip() {
+    let object = sample_object();
+
+    for format in [
+      BackupFormat::Json,
+      BackupFormat::Archive,
+      BackupFormat::Bson,
+    ] {
+      for compression in [BackupCompression::None, BackupCompression::Gzip] {
+        let content = object
+          .encode(format, JsonMode::Canonical, compression, None)
+          .unwrap();
+        let decoded = BackupObject::decode(&content, format, compression, None).unwrap();
+
+        assert_eq!(decoded.manifest, object.manifest);
+        assert_eq!(
+          decoded.collections[0].documents,
+          object.collections[0].documents
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn backup_object_encrypted_roundtrip() {
+    let key = generate_key();
+    let object = sample_object();
+    let content = object
+      .encode(
+        BackupFormat::Json,
+        JsonMode::Canonical,
+        BackupCompression::Gzip,
+        Some(&key),
+      )
+      .unwrap();
+
+    assert!(
+      BackupObject::decode(&content, BackupFormat::Json, BackupCompression::Gzip, None).is_err()
+    );
+
+    let decoded = BackupObject::decode(
+      &content,
+      BackupFormat::Json,
+      BackupCompression::Gzip,
+      Some(&key),
+    )
+    .unwrap();
+    assert_eq!(decoded.manifest, object.manifest);
+  }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{backups::OplogPosition, datastores::Datastore};
+
+/// Where the next incremental backup resumes the oplog, stored next to the backup objects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OplogState {
+  /// Object name of the full backup the incremental backups are chained to.
+  pub base: String,
+  pub position: OplogPosition,
+}
+
+impl OplogState {
+  pub fn object_name(backup_name: &str) -> String {
+    format!("backup_{backup_name}.oplog_state.json")
+  }
+
+  /// Loads the state of a backup, or `None` when no full backup recorded an oplog position yet.
+  pub fn load(datastore: &dyn Datastore, backup_name: &str) -> Result<Option<Self>, String> {
+    let object_name = Self::object_name(backup_name);
+    if !datastore.object_exists(&object_name) {
+      return Ok(None);
+    }
+
+    let content = datastore.get_object(object_name)?;
+    serde_json::from_slice(&content)
+      .map(Some)
+      .map_err(|err| format!("Invalid oplog state: {}", err))
+  }
+
+  pub fn save(&self, datastore: &dyn Datastore, backup_name: &str) -> Result<(), String> {
+    let object_name = Self::object_name(backup_name);
+    let content =
+      serde_json::to_vec(self).map_err(|err| format!("Cannot serialize oplog state: {}", err))?;
+
+    datastore.replace_object(&object_name, &content)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::{
+    backups::{OplogPosition, oplog::OplogState},
+    datastores::{Datastore, FilesystemDatastore},
+    tests::{clean_test_dir, get_test_dir_path},
+  };
+
+  #[test]
+  fn oplog_state_save_load() {
+    let test_dir_path = get_test_dir_path("oplog_state_save_load");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    assert_eq!(OplogState::load(&datastore, "cool").unwrap(), None);
+
+    let mut state = OplogState {
+      base: "backup_cool_100.json".to_string(),
+      position: OplogPosition {
+        time: 100,
+        increment: 1,
+      },
+    };
+    state.save(&datastore, "cool").unwrap();
+    state.position.time = 200;
+    state.save(&datastore, "cool").unwrap();
+
+    assert_eq!(OplogState::load(&datastore, "cool").unwrap(), Some(state));
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    clean_test_dir(test_dir_path);
+  }
+}
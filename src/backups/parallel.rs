@@ -0,0 +1,277 @@
+use std::{
+  collections::VecDeque,
+  sync::{Arc, Mutex},
+};
+
+use bson::{Bson, Document, RawDocumentBuf, Timestamp, doc, spec::ElementType};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinSet};
+
+use crate::db::DatabaseConnection;
+
+/// Collections are split into ranges of about this many documents, up to one per worker.
+pub const RANGE_DOCUMENTS: u64 = 500_000;
+/// Number of `_id` sampled for each range to pick its boundaries.
+pub const SAMPLES_PER_RANGE: usize = 10;
+
+/// A read of a collection, or of an `_id` range of it, run by a dump worker.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ReadTask {
+  pub database: String,
+  pub collection: String,
+  pub filter: Document,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub projection: Option<Document>,
+  /// Order of the read, by `_id` when set, so that an interrupted read goes on after the last
+  /// document it stored.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sort: Option<Document>,
+}
+
+/// Number of ranges a collection of `count` documents is split into.
+pub fn range_count(count: u64, parallelism: usize) -> usize {
+  usize::try_from(count.div_ceil(RANGE_DOCUMENTS))
+    .unwrap_or(usize::MAX)
+    .clamp(1, parallelism.max(1))
+}
+
+/// Picks the boundaries of `ranges` ranges among sorted sampled `_id`. MongoDB compares values of
+/// different types by their type only, so only the `_id` of the most common type are kept.
+pub fn boundaries(ids: &[Bson], ranges: usize) -> Vec<Bson> {
+  let mut counts: Vec<(ElementType, usize)> = Vec::new();
+  for id in ids {
+    match counts.iter_mut().find(|(kind, _)| *kind == type_class(id)) {
+      Some((_, count)) => *count += 1,
+      None => counts.push((type_class(id), 1)),
+    }
+  }
+  let Some((kind, _)) = counts.into_iter().max_by_key(|(_, count)| *count) else {
+    return Vec::new();
+  };
+
+  let ids: Vec<&Bson> = ids.iter().filter(|id| type_class(id) == kind).collect();
+  let mut boundaries: Vec<Bson> = (1..ranges)
+    .map(|i| ids[i * ids.len() / ranges].clone())
+    .collect();
+  boundaries.dedup();
+  boundaries
+}
+
+/// Numbers of every type compare with each other.
+fn type_class(value: &Bson) -> ElementType {
+  match value.element_type() {
+    ElementType::Int32 | ElementType::Int64 | ElementType::Decimal128 => ElementType::Double,
+    kind => kind,
+  }
+}
+
+/// Turns range boundaries into filters matching every document of `filter` exactly once.
+/// Documents whose `_id` is not of the type of the boundaries are left out by the ranges, so the
+/// last filter reads them.
+pub fn range_filters(filter: &Document, boundaries: &[Bson]) -> Vec<Document> {
+  let (Some(first), Some(last)) = (boundaries.first(), boundaries.last()) else {
+    return vec![filter.clone()];
+  };
+
+  let mut ranges = vec![doc! { "_id": { "$lt": first } }];
+  ranges.extend(
+    boundaries
+      .windows(2)
+      .map(|bounds| doc! { "_id": { "$gte": &bounds[0], "$lt": &bounds[1] } }),
+  );
+  ranges.push(doc! { "_id": { "$gte": last } });
+  ranges.push(doc! { "$nor": [{ "_id": { "$lt": first } }, { "_id": { "$gte": first } }] });
+
+  ranges
+    .into_iter()
+    .map(|range| match filter.is_empty() {
+      true => range,
+      false => doc! { "$and": [filter.clone(), range] },
+    })
+    .collect()
+}
+
+/// Matches the documents of `filter` sorted after `last_id` by `_id`. Compared in an expression,
+/// as `$gt` only matches `_id` of the same type while sorts order every type.
+pub fn resume_filter(filter: &Document, last_id: &Bson) -> Document {
+  let after = doc! { "$expr": { "$gt": ["$_id", { "$literal": last_id }] } };
+  match filter.is_empty() {
+    true => after,
+    false => doc! { "$and": [filter.clone(), after] },
+  }
+}
+
+/// Documents of a read task, the last batch of the task, possibly empty, marking it as read.
+#[derive(Debug)]
+pub struct ReadBatch {
+  /// Index of the task.
+  pub task: usize,
+  pub documents: Vec<RawDocumentBuf>,
+  pub last: bool,
+}
+
+/// Runs the reads on `parallelism` workers, handing their documents over to `on_batch` in batches
+/// as they are read, in order within each task. Each worker reads one task at a time, in a session
+/// sharing the snapshot at `snapshot_time` when given, so that no more than `parallelism` cursors
+/// are open at once.
+pub async fn read_all(
+  connection: &DatabaseConnection,
+  tasks: &[ReadTask],
+  parallelism: usize,
+  snapshot_time: Option<Timestamp>,
+  mut on_batch: impl FnMut(ReadBatch) -> Result<(), String>,
+) -> Result<(), String> {
+  let queue = Arc::new(Mutex::new(
+    tasks.iter().cloned().enumerate().collect::<VecDeque<_>>(),
+  ));
+  let workers_count = parallelism.clamp(1, tasks.len().max(1));
+  // Batches wait in the channel, so workers stop reading when they are not handled and memory
+  // stays bounded whatever the size of the collections
+  let (sender, mut receiver) = mpsc::channel(workers_count);
+
+  let mut workers = JoinSet::new();
+  for _ in 0..workers_count {
+    let connection = connection.clone();
+    let queue = queue.clone();
+    let sender = sender.clone();
+    workers.spawn(async move { read_queue(&connection, &queue, sender, snapshot_time).await });
+  }
+  drop(sender);
+
+  // A failing worker leaves the others reading the remaining tasks, which are handled before
+  // returning its error
+  while let Some(batch) = receiver.recv().await {
+    on_batch(batch)?;
+  }
+  while let Some(res) = workers.join_next().await {
+    res.map_err(|err| format!("Dump worker failed: {}", err))??;
+  }
+  Ok(())
+}
+
+async fn read_queue(
+  connection: &DatabaseConnection,
+  queue: &Mutex<VecDeque<(usize, ReadTask)>>,
+  sender: mpsc::Sender<ReadBatch>,
+  snapshot_time: Option<Timestamp>,
+) -> Result<(), String> {
+  let mut session = match snapshot_time {
+    Some(time) => Some(
+      connection
+        .start_snapshot_session_at(time)
+        .await
+        .map_err(|err| format!("Cannot start snapshot session: {}", err))?,
+    ),
+    None => None,
+  };
+
+  loop {
+    let next = queue
+      .lock()
+      .map(|mut queue| queue.pop_front())
+      .unwrap_or(None);
+    let Some((i, task)) = next else {
+      return Ok(());
+    };
+
+    connection
+      .find_batches(
+        &task.database,
+        &task.collection,
+        task.filter,
+        FindOptions::builder()
+          .projection(task.projection)
+          .sort(task.sort)
+          .build(),
+        session.as_mut(),
+        |documents, last| {
+          let sender = sender.clone();
+          async move {
+            let batch = ReadBatch {
+              task: i,
+              documents,
+              last,
+            };
+            sender.send(batch).await.is_ok()
+          }
+        },
+      )
+      .await
+      .map_err(|err| format!("Cannot read {}.{}: {}", task.database, task.collection, err))?;
+    if sender.is_closed() {
+      return Ok(());
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{Bson, doc, oid::ObjectId};
+
+  use crate::backups::parallel::{RANGE_DOCUMENTS, boundaries, range_count, range_filters};
+
+  #[test]
+  fn parallel_range_count() {
+    assert_eq!(range_count(0, 4), 1);
+    assert_eq!(range_count(RANGE_DOCUMENTS, 4), 1);
+    assert_eq!(range_count(RANGE_DOCUMENTS + 1, 4), 2);
+    assert_eq!(range_count(RANGE_DOCUMENTS * 100, 4), 4);
+    assert_eq!(range_count(RANGE_DOCUMENTS * 100, 1), 1);
+  }
+
+  #[test]
+  fn parallel_boundaries() {
+    let ids: Vec<Bson> = (0..12).map(|i| Bson::Int32(i * 10)).collect();
+    assert_eq!(
+      boundaries(&ids, 4),
+      vec![Bson::Int32(30), Bson::Int32(60), Bson::Int32(90)]
+    );
+    assert!(boundaries(&ids, 1).is_empty());
+    assert!(boundaries(&[], 4).is_empty());
+
+    // Numbers of every type are compared together, other types are left to the last filter
+    let id = ObjectId::new();
+    let mixed = vec![
+      Bson::Int32(1),
+      Bson::Int64(2),
+      Bson::Double(3.5),
+      Bson::Int32(4),
+      Bson::ObjectId(id),
+    ];
+    assert_eq!(boundaries(&mixed, 2), vec![Bson::Double(3.5)]);
+
+    let duplicated = vec![Bson::Int32(1); 8];
+    assert_eq!(boundaries(&duplicated, 4), vec![Bson::Int32(1)]);
+  }
+
+  #[test]
+  fn parallel_range_filters() {
+    let bounds = vec![Bson::Int32(10), Bson::Int32(20)];
+
+    assert_eq!(
+      range_filters(&doc! {}, &bounds),
+      vec![
+        doc! { "_id": { "$lt": 10 } },
+        doc! { "_id": { "$gte": 10, "$lt": 20 } },
+        doc! { "_id": { "$gte": 20 } },
+        doc! { "$nor": [{ "_id": { "$lt": 10 } }, { "_id": { "$gte": 10 } }] },
+      ]
+    );
+    assert_eq!(
+      range_filters(&doc! { "active": true }, &bounds[..1]),
+      vec![
+        doc! { "$and": [{ "active": true }, { "_id": { "$lt": 10 } }] },
+        doc! { "$and": [{ "active": true }, { "_id": { "$gte": 10 } }] },
+        doc! { "$and": [
+          { "active": true },
+          { "$nor": [{ "_id": { "$lt": 10 } }, { "_id": { "$gte": 10 } }] },
+        ] },
+      ]
+    );
+    assert_eq!(
+      range_filters(&doc! { "active": true }, &[]),
+      vec![doc! { "active": true }]
+    );
+  }
+}
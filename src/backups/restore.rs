@@ -0,0 +1,731 @@
+use bson::{Document, RawDocumentBuf, doc};
+use mongodb::Database;
+
+use crate::{
+  backups::{BackupKind, BackupManifest, BackupObject, CollectionManifest, ShardingManifest},
+  db::{
+    DatabaseConnection,
+    connection::{self, NAMESPACE_EXISTS, UNAUTHORIZED},
+  },
+  utils::logger::Logger,
+};
+
+const INSERT_BATCH_SIZE: usize = 1000;
+/// Collections of the `admin` database users and roles are merged from.
+const TEMP_USERS_COLLECTION: &str = "mbm_temp_users";
+const TEMP_ROLES_COLLECTION: &str = "mbm_temp_roles";
+const REPLAY_BATCH_SIZE: usize = 1000;
+/// Keeps `applyOps` commands well under the 16MB BSON document limit.
+const REPLAY_BATCH_BYTES: usize = 8 * 1024 * 1024;
+
+pub struct RestoreOptions {
+  pub connection_string: String,
+  /// Restores every collection into this database instead of the one it was dumped from.
+  pub target_database: Option<String>,
+  /// Drops existing collections before restoring them.
+  pub drop: bool,
+  /// Builds indexes before loading the documents instead of after, which is slower but rejects
+  /// duplicates of unique indexes as they are inserted.
+  pub indexes_first: bool,
+  /// Restores the users and roles of the backup, merged with those of the server.
+  pub users_and_roles: bool,
+}
+
+impl RestoreOptions {
+  /// The database `database` is restored into, among the `databases` of a backup. When a backup
+  /// of several databases is restored into a target database, each one goes into
+  /// `<target>_<database>` so that their collections do not collide.
+  pub fn target(&self, databases: &[String], database: &str) -> String {
+    match &self.target_database {
+      Some(target) if databases.len() > 1 => format!("{target}_{database}"),
+      Some(target) => target.clone(),
+      None => database.to_string(),
+    }
+  }
+}
+
+pub struct RestoreEngine<'a> {
+  options: &'a RestoreOptions,
+}
+
+impl<'a> RestoreEngine<'a> {
+  pub fn new(options: &'a RestoreOptions) -> Self {
+    Self { options }
+  }
+
+  pub async fn restore(&self, object: &BackupObject) -> Result<(), String> {
+    self.restore_to(object, &[], None).await
+  }
+
+  /// Restores a full backup, then replays the oplog entries of its incremental backups and the
+  /// events of its change segments written before the `until` Unix timestamp, or all of them when
+  /// not provided.
+  pub async fn restore_to(
+    &self,
+    base: &BackupObject,
+    segments: &[BackupObject],
+    until: Option<i64>,
+  ) -> Result<(), String> {
+    if base.manifest.kind == BackupKind::Changes {
+      return Err("Cannot restore change events on their own, restore a full backup".to_string());
+    }
+    if base.manifest.kind == BackupKind::Incremental {
+      return Err(format!(
+        "Cannot restore an incremental backup on its own, restore its base {} instead",
+        base.manifest.base.as_deref().unwrap_or("full backup")
+      ));
+    }
+
+    let mut connection = DatabaseConnection::new();
+    connection
+      .connect(&self.options.connection_string)
+      .await
+      .map_err(|err| format!("Cannot connect to database: {}", err))?;
+
+    let mut res = self.restore_collections(&connection, base).await;
+    if res.is_ok() {
+      res = self.restore_views(&connection, base).await;
+    }
+    if res.is_ok() && self.options.users_and_roles {
+      res = self.restore_users_and_roles(&connection, base).await;
+    }
+    if res.is_ok() && !segments.is_empty() {
+      res = self
+        .replay_oplog(&connection, base, segments, until.unwrap_or(i64::MAX))
+        .await;
+    }
+    let _ = connection.disconnect().await;
+    res
+  }
+
+  async fn replay_oplog(
+    &self,
+    connection: &DatabaseConnection,
+    base: &BackupObject,
+    segments: &[BackupObject],
+    until: i64,
+  ) -> Result<(), String> {
+    let base_databases = base.manifest.database_names();
+    let admin = connection
+      .database("admin")
+      .map_err(|err| err.to_string())?;
+
+    let mut applied = 0;
+    for segment in segments {
+      // Segments created before backups recorded their databases cover those of their base
+      let sources = if segment.manifest.databases.is_empty() {
+        &base_databases
+      } else {
+        &segment.manifest.databases
+      };
+      let target = |database: &str| {
+        sources
+          .iter()
+          .any(|source| source == database)
+          .then(|| self.options.target(&base_databases, database))
+      };
+
+      for dump in &segment.collections {
+        let operations = match segment.manifest.kind {
+          BackupKind::Changes => change_operations(&dump.documents, &target, until)?,
+          _ => replay_operations(&dump.documents, &target, until)?,
+        };
+        for batch in replay_batches(&operations) {
+          admin
+            .run_command(doc! { "applyOps": batch })
+            .await
+            .map_err(|err| format!("Cannot replay oplog entries: {}", err))?;
+          applied += batch.len();
+        }
+      }
+    }
+
+    Logger::info(&format!(
+      "Replayed {} operations from {} incremental backups and change segments",
+      applied,
+      segments.len()
+    ));
+
+    Ok(())
+  }
+
+  async fn restore_collections(
+    &self,
+    connection: &DatabaseConnection,
+    object: &BackupObject,
+  ) -> Result<(), String> {
+    let databases = object.manifest.database_names();
+    let sharded = object
+      .manifest
+      .collections
+      .iter()
+      .any(|manifest| manifest.sharding.is_some());
+    let is_mongos = sharded && connection.is_mongos().await.unwrap_or(false);
+    if sharded && !is_mongos {
+      Logger::warn(
+        "The backup holds sharded collections but the server is not a mongos, they are restored \
+         unsharded",
+      );
+    }
+
+    for (manifest, dump) in object.manifest.collections.iter().zip(&object.collections) {
+      let database = self.options.target(&databases, &manifest.database);
+      let db = connection
+        .database(&database)
+        .map_err(|err| err.to_string())?;
+      let collection = db.collection::<RawDocumentBuf>(&manifest.name);
+
+      if manifest.is_partial() {
+        Logger::warn(&format!(
+          "The backup of {} was taken with a filter or projection and only holds part of the \
+           collection{}",
+          manifest.namespace(),
+          if self.options.drop {
+            ", documents left out of it are lost by dropping the collection"
+          } else {
+            ""
+          }
+        ));
+      }
+
+      if self.options.drop {
+        collection
+          .drop()
+          .await
+          .map_err(|err| format!("Cannot drop {}.{}: {}", database, manifest.name, err))?;
+      }
+
+      // Empty collections are not created by any insert, nor time-series and capped collections
+      // with their options
+      Self::create_collection(&db, &manifest.name, &manifest.options).await?;
+      // Sharding the empty collection spreads the documents over the shards as they are inserted
+      if let Some(sharding) = manifest.sharding.as_ref().filter(|_| is_mongos) {
+        Self::shard_collection(connection, &database, &manifest.name, sharding).await?;
+      }
+      if self.options.indexes_first {
+        Self::create_indexes(&db, manifest).await?;
+      }
+
+      // Documents were valid when dumped, validators may have changed since
+      for batch in dump.documents.chunks(INSERT_BATCH_SIZE) {
+        collection
+          .insert_many(batch)
+          .bypass_document_validation(true)
+          .await
+          .map_err(|err| format!("Cannot restore {}.{}: {}", database, manifest.name, err))?;
+      }
+
+      if !self.options.indexes_first {
+        Self::create_indexes(&db, manifest).await?;
+      }
+
+      Logger::info(&format!(
+        "Restored {} documents into {}.{}",
+        dump.documents.len(),
+        database,
+        manifest.name
+      ));
+    }
+
+    Ok(())
+  }
+
+  async fn restore_views(
+    &self,
+    connection: &DatabaseConnection,
+    object: &BackupObject,
+  ) -> Result<(), String> {
+    let databases = object.manifest.database_names();
+
+    for view in &object.manifest.views {
+      let database = self.options.target(&databases, &view.database);
+      let db = connection
+        .database(&database)
+        .map_err(|err| err.to_string())?;
+
+      if self.options.drop {
+        db.collection::<Document>(&view.name)
+          .drop()
+          .await
+          .map_err(|err| format!("Cannot drop {}.{}: {}", database, view.name, err))?;
+      }
+      Self::create_collection(&db, &view.name, &view.options).await?;
+    }
+
+    if !object.manifest.views.is_empty() {
+      Logger::info(&format!("Restored {} views", object.manifest.views.len()));
+    }
+    Ok(())
+  }
+
+  /// Merges the users and roles of a backup with those of the server, the way `mongorestore`
+  /// does, through temporary collections of the `admin` database.
+  async fn restore_users_and_roles(
+    &self,
+    connection: &DatabaseConnection,
+    object: &BackupObject,
+  ) -> Result<(), String> {
+    let manifest = &object.manifest;
+    if manifest.users.is_empty() && manifest.roles.is_empty() {
+      Logger::info("The backup holds no users or roles to restore");
+      return Ok(());
+    }
+    if self.options.target_database.is_some() {
+      Logger::warn("Users and roles are not restored into another database, skipping them");
+      return Ok(());
+    }
+
+    let admin = connection
+      .database("admin")
+      .map_err(|err| err.to_string())?;
+    let res = Self::merge_users_and_roles(&admin, manifest, self.options.drop).await;
+    for collection in [TEMP_USERS_COLLECTION, TEMP_ROLES_COLLECTION] {
+      let _ = admin.collection::<Document>(collection).drop().await;
+    }
+
+    match res {
+      Ok(()) => {
+        Logger::info(&format!(
+          "Restored {} users and {} roles",
+          manifest.users.len(),
+          manifest.roles.len()
+        ));
+        Ok(())
+      }
+      Err(err) if connection::command_error_code(&err) == Some(UNAUTHORIZED) => {
+        Logger::warn(&format!(
+          "Not allowed to restore users and roles, skipping them: {}",
+          err
+        ));
+        Ok(())
+      }
+      Err(err) => Err(format!("Cannot restore users and roles: {}", err)),
+    }
+  }
+
+  async fn merge_users_and_roles(
+    admin: &Database,
+    manifest: &BackupManifest,
+    drop: bool,
+  ) -> mongodb::error::Result<()> {
+    for (collection, documents) in [
+      (TEMP_USERS_COLLECTION, &manifest.users),
+      (TEMP_ROLES_COLLECTION, &manifest.roles),
+    ] {
+      let collection = admin.collection::<Document>(collection);
+      collection.drop().await?;
+      if !documents.is_empty() {
+        collection.insert_many(documents).await?;
+      }
+    }
+
+    // Merging database by database keeps `drop` from removing users of other databases
+    for database in manifest.database_names() {
+      admin
+        .run_command(doc! {
+          "_mergeAuthzCollections": 1,
+          "tempUsersCollection": format!("admin.{TEMP_USERS_COLLECTION}"),
+          "tempRolesCollection": format!("admin.{TEMP_ROLES_COLLECTION}"),
+          "drop": drop,
+          "db": database,
+        })
+        .await?;
+    }
+    Ok(())
+  }
+
+  /// Creates a collection or a view with the options it was dumped with. Existing collections are
+  /// kept as they are.
+  async fn create_collection(db: &Database, name: &str, options: &Document) -> Result<(), String> {
+    match db.run_command(create_command(name, options)).await {
+      Ok(_) => Ok(()),
+      Err(err) if connection::command_error_code(&err) == Some(NAMESPACE_EXISTS) => {
+        if !options.is_empty() {
+          Logger::warn(&format!(
+            "{}.{} already exists, its options are left unchanged",
+            db.name(),
+            name
+          ));
+        }
+        Ok(())
+      }
+      Err(err) => Err(format!("Cannot create {}.{}: {}", db.name(), name, err)),
+    }
+  }
+
+  async fn shard_collection(
+    connection: &DatabaseConnection,
+    database: &str,
+    name: &str,
+    sharding: &ShardingManifest,
+  ) -> Result<(), String> {
+    let namespace = format!("{}.{}", database, name);
+    let res = async {
+      connection.enable_sharding(database).await?;
+      connection
+        .shard_collection(
+          &namespace,
+          sharding.key.clone(),
+          sharding.unique,
+          sharding.options.contains_key("defaultCollation"),
+        )
+        .await?;
+      if sharding.options.get_bool("noBalance").unwrap_or(false) {
+        connection.disable_balancing(&namespace).await?;
+      }
+      Ok::<_, mongodb::error::Error>(())
+    }
+    .await;
+
+    res.map_err(|err| format!("Cannot shard {}: {}", namespace, err))?;
+    Logger::info(&format!("Sharded {} with key {}", namespace, sharding.key));
+    Ok(())
+  }
+
+  async fn create_indexes(db: &Database, manifest: &CollectionManifest) -> Result<(), String> {
+    let indexes: Vec<Document> = manifest
+      .indexes
+      .iter()
+      .filter(|index| index.name != "_id_")
+      .map(|index| index.spec())
+      .collect();
+    if indexes.is_empty() {
+      return Ok(());
+    }
+
+    db.run_command(doc! { "createIndexes": &manifest.name, "indexes": indexes })
+      .await
+      .map_err(|err| {
+        format!(
+          "Cannot create indexes {} on {}.{}: {}",
+          manifest.index_names().join(", "),
+          db.name(),
+          manifest.name,
+          err
+        )
+      })?;
+    Ok(())
+  }
+}
+
+/// The `create` command of a collection, with its restorable options.
+pub fn create_command(name: &str, options: &Document) -> Document {
+  let mut command = doc! { "create": name };
+  command.extend(create_options(options));
+  command
+}
+
+/// Turns the options returned by `listCollections` into options accepted by `create`. Time-series
+/// collections report bucketing parameters derived from their granularity, which `create` rejects
+/// along with it.
+pub fn create_options(options: &Document) -> Document {
+  let mut options = options.clone();
+  if let Ok(timeseries) = options.get_document_mut("timeseries")
+    && timeseries.contains_key("granularity")
+  {
+    timeseries.remove("bucketMaxSpanSeconds");
+    timeseries.remove("bucketRoundingSeconds");
+  }
+  options
+}
+
+/// Turns the oplog entries written before the `until` Unix timestamp into `applyOps` operations,
+/// unwrapping the operations applied by transactions. `target` gives the database to replay the
+/// operations of a database into, or `None` to leave them out.
+pub fn replay_operations(
+  entries: &[RawDocumentBuf],
+  target: &dyn Fn(&str) -> Option<String>,
+  until: i64,
+) -> Result<Vec<Document>, String> {
+  let mut operations = Vec::new();
+
+  for entry in entries {
+    let entry =
+      Document::try_from(entry.as_ref()).map_err(|err| format!("Invalid oplog entry: {}", err))?;
+    let timestamp = entry
+      .get_timestamp("ts")
+      .map_err(|err| format!("Invalid oplog entry: {}", err))?;
+    if timestamp.time as i64 >= until {
+      break;
+    }
+
+    match entry
+      .get_document("o")
+      .and_then(|o| o.get_array("applyOps"))
+    {
+      Ok(nested) if entry.get_str("op").ok() == Some("c") => operations.extend(
+        nested
+          .iter()
+          .filter_map(|operation| replay_operation(operation.as_document()?, target)),
+      ),
+      _ => operations.extend(replay_operation(&entry, target)),
+    }
+  }
+
+  Ok(operations)
+}
+
+/// Turns the change events recorded before the `until` Unix timestamp into `applyOps` operations,
+/// like `replay_operations`. Inserts, updates and replaces write the full document, so that events
+/// recorded twice give the same result, and are left out when the document was deleted since.
+/// Events other than writes and collection drops are left out.
+pub fn change_operations(
+  events: &[RawDocumentBuf],
+  target: &dyn Fn(&str) -> Option<String>,
+  until: i64,
+) -> Result<Vec<Document>, String> {
+  let mut operations = Vec::new();
+
+  for event in events {
+    let event =
+      Document::try_from(event.as_ref()).map_err(|err| format!("Invalid change event: {}", err))?;
+    let timestamp = event
+      .get_timestamp("clusterTime")
+      .map_err(|err| format!("Invalid change event: {}", err))?;
+    if timestamp.time as i64 >= until {
+      break;
+    }
+
+    let Ok(ns) = event.get_document("ns") else {
+      continue;
+    };
+    let (Ok(database), Ok(collection)) = (ns.get_str("db"), ns.get_str("coll")) else {
+      continue;
+    };
+    let Some(database) = target(database) else {
+      continue;
+    };
+    let namespace = format!("{database}.{collection}");
+    let (document, key) = (
+      event.get_document("fullDocument"),
+      event.get_document("documentKey"),
+    );
+
+    let operation = match (event.get_str("operationType"), document, key) {
+      (Ok("insert"), Ok(document), _) => doc! { "op": "i", "ns": namespace, "o": document },
+      (Ok("update" | "replace"), Ok(document), Ok(key)) => {
+        doc! { "op": "u", "ns": namespace, "o": document, "o2": key }
+      }
+      (Ok("delete"), _, Ok(key)) => doc! { "op": "d", "ns": namespace, "o": key },
+      (Ok("drop"), _, _) => doc! {
+        "op": "c",
+        "ns": format!("{database}.$cmd"),
+        "o": { "drop": collection },
+      },
+      _ => continue,
+    };
+    operations.push(operation);
+  }
+
+  Ok(operations)
+}
+
+/// Keeps the fields `applyOps` needs, leaving out the collection UUID which differs once restored.
+fn replay_operation(entry: &Document, target: &dyn Fn(&str) -> Option<String>) -> Option<Document> {
+  let op = entry.get_str("op").ok()?;
+  let (database, collection) = entry.get_str("ns").ok()?.split_once('.')?;
+  if op == "n" {
+    return None;
+  }
+
+  let mut operation = doc! {
+    "op": op,
+    "ns": format!("{}.{collection}", target(database)?),
+    "o": entry.get_document("o").ok()?.clone(),
+  };
+  if let Ok(o2) = entry.get_document("o2") {
+    operation.insert("o2", o2.clone());
+  }
+  Some(operation)
+}
+
+fn replay_batches(operations: &[Document]) -> Vec<&[Document]> {
+  let mut batches = Vec::new();
+  let (mut start, mut bytes) = (0, 0);
+
+  for (i, operation) in operations.iter().enumerate() {
+    let size = RawDocumentBuf::try_from(operation).map_or(0, |raw| raw.as_bytes().len());
+    if i > start && (i - start == REPLAY_BATCH_SIZE || bytes + size > REPLAY_BATCH_BYTES) {
+      batches.push(&operations[start..i]);
+      (start, bytes) = (i, 0);
+    }
+    bytes += size;
+  }
+  if start < operations.len() {
+    batches.push(&operations[start..]);
+  }
+
+  batches
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{Timestamp, doc, oid::ObjectId, rawdoc};
+
+  use crate::backups::{
+    RestoreOptions,
+    restore::{
+      REPLAY_BATCH_SIZE, change_operations, create_command, create_options, replay_batches,
+      replay_operations,
+    },
+  };
+
+  #[test]
+  fn restore_replay_operations() {
+    let ts = |time| Timestamp { time, increment: 1 };
+    let id = ObjectId::new();
+    let entries = vec![
+      rawdoc! { "ts": ts(10), "op": "i", "ns": "database.users", "ui": id, "o": { "_id": 1 } },
+      rawdoc! { "ts": ts(11), "op": "n", "ns": "database.users", "o": {} },
+      rawdoc! { "ts": ts(12), "op": "c", "ns": "admin.$cmd", "o": { "applyOps": [
+        { "op": "u", "ns": "database.users", "o": { "$set": { "a": 1 } }, "o2": { "_id": 1 } },
+        { "op": "d", "ns": "other.users", "o": { "_id": 1 } },
+      ] } },
+      rawdoc! { "ts": ts(13), "op": "c", "ns": "database.$cmd", "o": { "drop": "users" } },
+      rawdoc! { "ts": ts(20), "op": "d", "ns": "database.users", "o": { "_id": 1 } },
+    ];
+
+    let restored = |database: &str| (database == "database").then(|| "restored".to_string());
+    let same = |database: &str| (database == "database").then(|| database.to_string());
+
+    assert_eq!(
+      replay_operations(&entries, &restored, 20).unwrap(),
+      vec![
+        doc! { "op": "i", "ns": "restored.users", "o": { "_id": 1 } },
+        doc! { "op": "u", "ns": "restored.users", "o": { "$set": { "a": 1 } }, "o2": { "_id": 1 } },
+        doc! { "op": "c", "ns": "restored.$cmd", "o": { "drop": "users" } },
+      ]
+    );
+    assert_eq!(
+      replay_operations(&entries, &same, i64::MAX).unwrap().len(),
+      4
+    );
+    assert!(replay_operations(&entries, &same, 10).unwrap().is_empty());
+  }
+
+  #[test]
+  fn restore_change_operations() {
+    let ts = |time| Timestamp { time, increment: 1 };
+    let ns = |coll| rawdoc! { "db": "app", "coll": coll };
+    let id = ObjectId::new();
+    let events = vec![
+      rawdoc! {
+        "operationType": "insert", "clusterTime": ts(10), "ns": ns("users"),
+        "documentKey": { "_id": id }, "fullDocument": { "_id": id, "name": "Alice" },
+      },
+      rawdoc! {
+        "operationType": "update", "clusterTime": ts(20), "ns": ns("users"),
+        "documentKey": { "_id": id }, "fullDocument": { "_id": id, "name": "Bob" },
+        "updateDescription": { "updatedFields": { "name": "Bob" }, "removedFields": [] },
+      },
+      // Deleted by the time the full document was looked up
+      rawdoc! {
+        "operationType": "update", "clusterTime": ts(25), "ns": ns("users"),
+        "documentKey": { "_id": id }, "fullDocument": null,
+      },
+      rawdoc! {
+        "operationType": "delete", "clusterTime": ts(30), "ns": ns("users"),
+        "documentKey": { "_id": id },
+      },
+      rawdoc! {
+        "operationType": "insert", "clusterTime": ts(35), "ns": { "db": "other", "coll": "x" },
+        "documentKey": { "_id": 1 }, "fullDocument": { "_id": 1 },
+      },
+      rawdoc! { "operationType": "drop", "clusterTime": ts(40), "ns": ns("logs") },
+      rawdoc! { "operationType": "invalidate", "clusterTime": ts(45) },
+      rawdoc! {
+        "operationType": "delete", "clusterTime": ts(50), "ns": ns("users"),
+        "documentKey": { "_id": id },
+      },
+    ];
+    let target = |database: &str| (database == "app").then(|| "restored".to_string());
+
+    assert_eq!(
+      change_operations(&events, &target, 50).unwrap(),
+      vec![
+        doc! { "op": "i", "ns": "restored.users", "o": { "_id": id, "name": "Alice" } },
+        doc! {
+          "op": "u", "ns": "restored.users", "o": { "_id": id, "name": "Bob" },
+          "o2": { "_id": id },
+        },
+        doc! { "op": "d", "ns": "restored.users", "o": { "_id": id } },
+        doc! { "op": "c", "ns": "restored.$cmd", "o": { "drop": "logs" } },
+      ]
+    );
+    assert!(change_operations(&[rawdoc! {}], &target, 50).is_err());
+  }
+
+  #[test]
+  fn restore_create_options() {
+    let timeseries = doc! {
+      "timeseries": {
+        "timeField": "timestamp",
+        "metaField": "sensor",
+        "granularity": "minutes",
+        "bucketMaxSpanSeconds": 86400,
+      },
+      "expireAfterSeconds": 3600_i64,
+    };
+    assert_eq!(
+      create_options(&timeseries),
+      doc! {
+        "timeseries": { "timeField": "timestamp", "metaField": "sensor", "granularity": "minutes" },
+        "expireAfterSeconds": 3600_i64,
+      }
+    );
+
+    let custom_buckets = doc! {
+      "timeseries": {
+        "timeField": "timestamp",
+        "bucketMaxSpanSeconds": 600,
+        "bucketRoundingSeconds": 600,
+      },
+    };
+    assert_eq!(create_options(&custom_buckets), custom_buckets);
+
+    let capped = doc! { "capped": true, "size": 4096_i64, "max": 100 };
+    assert_eq!(create_options(&capped), capped);
+  }
+
+  #[test]
+  fn restore_create_command() {
+    // Empty collections without options are created too, as no document creates them
+    assert_eq!(
+      create_command("empty", &doc! {}),
+      doc! { "create": "empty" }
+    );
+    assert_eq!(
+      create_command("logs", &doc! { "capped": true, "size": 4096_i64 }),
+      doc! { "create": "logs", "capped": true, "size": 4096_i64 }
+    );
+  }
+
+  #[test]
+  fn restore_options_target() {
+    let mut options = RestoreOptions {
+      connection_string: String::new(),
+      target_database: None,
+      drop: false,
+      indexes_first: false,
+      users_and_roles: false,
+    };
+    let databases = vec!["app".to_string(), "billing".to_string()];
+
+    assert_eq!(options.target(&databases, "app"), "app");
+    options.target_database = Some("restored".to_string());
+    assert_eq!(options.target(&databases[..1], "app"), "restored");
+    assert_eq!(options.target(&databases, "billing"), "restored_billing");
+  }
+
+  #[test]
+  fn restore_replay_batches() {
+    let operations = vec![doc! { "op": "i" }; REPLAY_BATCH_SIZE * 2 + 1];
+    let batches = replay_batches(&operations);
+
+    assert_eq!(
+      batches.iter().map(|b| b.len()).collect::<Vec<_>>(),
+      vec![REPLAY_BATCH_SIZE, REPLAY_BATCH_SIZE, 1]
+    );
+    assert!(replay_batches(&[]).is_empty());
+  }
+}
@@ -0,0 +1,154 @@
+use std::{
+  sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+  },
+  time::Duration,
+};
+
+use chrono::{Local, Timelike};
+
+use crate::{
+  backups::{BackupEngine, ChangeStreamRecorder, RestoreDrill},
+  utils::{
+    config::{Backup, Config},
+    cron::CronSchedule,
+    logger::Logger,
+  },
+};
+
+enum JobKind {
+  Backup,
+  IncrementalBackup,
+  RestoreDrill,
+}
+
+struct Job {
+  name: String,
+  backup: Backup,
+  kind: JobKind,
+  schedule: CronSchedule,
+  /// Set while a run of the job is in progress, so that runs never overlap.
+  running: Arc<AtomicBool>,
+}
+
+/// Clears the running flag of a job when its run ends, even by panicking.
+struct RunGuard(Arc<AtomicBool>);
+
+impl Drop for RunGuard {
+  fn drop(&mut self) {
+    self.0.store(false, Ordering::SeqCst);
+  }
+}
+
+/// Delay before reopening a failed change stream.
+const STREAM_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Runs the enabled backup schedules, incremental backups, restore drills and change streams until
+/// the process is stopped.
+pub struct Scheduler {
+  jobs: Vec<Job>,
+  streams: Vec<(String, Backup)>,
+}
+
+impl Scheduler {
+  pub fn new(config: &Config) -> Result<Self, String> {
+    let mut jobs = Vec::new();
+    let mut streams = Vec::new();
+
+    for (name, backup) in config.backups() {
+      if backup.schedule.enabled {
+        jobs.push(Job {
+          name: name.to_string(),
+          backup: backup.clone(),
+          kind: JobKind::Backup,
+          schedule: CronSchedule::parse(&backup.schedule.cron)?,
+          running: Arc::default(),
+        });
+      }
+      if let Some(incremental) = backup.incremental.as_ref().filter(|i| i.enabled) {
+        jobs.push(Job {
+          name: name.to_string(),
+          backup: backup.clone(),
+          kind: JobKind::IncrementalBackup,
+          schedule: CronSchedule::parse(&incremental.cron)?,
+          running: Arc::default(),
+        });
+      }
+      if backup.change_stream.as_ref().is_some_and(|s| s.enabled) {
+        streams.push((name.to_string(), backup.clone()));
+      }
+      if let Some(drill) = backup.restore_drill.as_ref().filter(|d| d.enabled) {
+        jobs.push(Job {
+          name: name.to_string(),
+          backup: backup.clone(),
+          kind: JobKind::RestoreDrill,
+          schedule: CronSchedule::parse(&drill.cron)?,
+          running: Arc::default(),
+        });
+      }
+    }
+
+    Ok(Self { jobs, streams })
+  }
+
+  pub async fn run(&self) {
+    Logger::info(&format!(
+      "Scheduler started with {} jobs and {} change streams",
+      self.jobs.len(),
+      self.streams.len()
+    ));
+
+    for (name, backup) in self.streams.clone() {
+      tokio::spawn(async move {
+        loop {
+          if let Err(err) = ChangeStreamRecorder::new(&name, &backup).run().await {
+            Logger::error(&format!("Change stream of {} failed: {}", name, err));
+          }
+          tokio::time::sleep(STREAM_RETRY_DELAY).await;
+        }
+      });
+    }
+
+    loop {
+      let wait = 60 - Local::now().second() as u64;
+      tokio::time::sleep(Duration::from_secs(wait)).await;
+
+      let now = Local::now();
+      for job in self.jobs.iter().filter(|job| job.schedule.matches(&now)) {
+        // A second run would resume the checkpoint of the first one while it is still writing
+        if job.running.swap(true, Ordering::SeqCst) {
+          Logger::warn(&format!(
+            "The previous run of {} is still in progress, skipping this one",
+            job.name
+          ));
+          continue;
+        }
+        let guard = RunGuard(job.running.clone());
+        let name = job.name.clone();
+        let backup = job.backup.clone();
+
+        match job.kind {
+          JobKind::Backup => tokio::spawn(async move {
+            let _guard = guard;
+            if let Err(err) = BackupEngine::new(&name, &backup).run().await {
+              Logger::error(&format!("Backup {} failed: {}", name, err));
+            }
+          }),
+          JobKind::IncrementalBackup => tokio::spawn(async move {
+            let _guard = guard;
+            if let Err(err) = BackupEngine::new(&name, &backup).run_incremental().await {
+              Logger::error(&format!("Incremental backup {} failed: {}", name, err));
+            }
+          }),
+          JobKind::RestoreDrill => tokio::spawn(async move {
+            let _guard = guard;
+            if let Err(err) = RestoreDrill::new(&name, &backup).run().await {
+              Logger::error(&format!("Restore drill of {} could not run: {}", name, err));
+            }
+          }),
+        };
+      }
+    }
+  }
+}
@@ -0,0 +1,98 @@
+use bson::{Bson, Document};
+
+/// Fields of the `admin.system.users` documents.
+const USER_FIELDS: [&str; 7] = [
+  "userId",
+  "user",
+  "db",
+  "credentials",
+  "roles",
+  "customData",
+  "authenticationRestrictions",
+];
+/// Fields of the `admin.system.roles` documents.
+const ROLE_FIELDS: [&str; 5] = [
+  "role",
+  "db",
+  "privileges",
+  "roles",
+  "authenticationRestrictions",
+];
+
+/// Turns a user returned by `usersInfo` into its `admin.system.users` document.
+pub fn system_user(info: &Document) -> Option<Document> {
+  system_document(info, info.get_str("user").ok()?, &USER_FIELDS)
+}
+
+/// Turns a role returned by `rolesInfo` into its `admin.system.roles` document.
+pub fn system_role(info: &Document) -> Option<Document> {
+  system_document(info, info.get_str("role").ok()?, &ROLE_FIELDS)
+}
+
+fn system_document(info: &Document, name: &str, fields: &[&str]) -> Option<Document> {
+  let database = info.get_str("db").ok()?;
+
+  let mut document = Document::new();
+  document.insert("_id", Bson::String(format!("{database}.{name}")));
+  for field in fields {
+    if let Some(value) = info.get(*field) {
+      document.insert(*field, value.clone());
+    }
+  }
+  Some(document)
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::doc;
+
+  use crate::backups::security::{system_role, system_user};
+
+  #[test]
+  fn security_system_user() {
+    let info = doc! {
+      "_id": "app.jane",
+      "user": "jane",
+      "db": "app",
+      "credentials": { "SCRAM-SHA-256": { "iterationCount": 15000 } },
+      "roles": [{ "role": "readWrite", "db": "app" }],
+      "mechanisms": ["SCRAM-SHA-256"],
+    };
+
+    assert_eq!(
+      system_user(&info),
+      Some(doc! {
+        "_id": "app.jane",
+        "user": "jane",
+        "db": "app",
+        "credentials": { "SCRAM-SHA-256": { "iterationCount": 15000 } },
+        "roles": [{ "role": "readWrite", "db": "app" }],
+      })
+    );
+    assert_eq!(system_user(&doc! { "db": "app" }), None);
+  }
+
+  #[test]
+  fn security_system_role() {
+    let info = doc! {
+      "role": "reporting",
+      "db": "app",
+      "isBuiltin": false,
+      "roles": [],
+      "inheritedRoles": [],
+      "privileges": [{ "resource": { "db": "app", "collection": "" }, "actions": ["find"] }],
+      "inheritedPrivileges": [],
+    };
+
+    assert_eq!(
+      system_role(&info),
+      Some(doc! {
+        "_id": "app.reporting",
+        "role": "reporting",
+        "db": "app",
+        "privileges": [{ "resource": { "db": "app", "collection": "" }, "actions": ["find"] }],
+        "roles": [],
+      })
+    );
+  }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+
+use crate::{
+  db::DatabaseConnection,
+  utils::{
+    config::Backup,
+    pattern::{Pattern, glob_match},
+  },
+};
+
+/// Databases only backed up when named explicitly in `databases`.
+pub const SYSTEM_DATABASES: [&str; 3] = ["admin", "config", "local"];
+
+/// Picks the databases of a backup among the `available` ones. Without `databases` patterns, the
+/// database of the connection string is used, or every non-system database when it has none.
+pub fn select_databases(
+  backup: &Backup,
+  available: &[String],
+  default_database: Option<&str>,
+) -> Vec<String> {
+  let mut databases: Vec<String> = if !backup.databases.is_empty() {
+    available
+      .iter()
+      .filter(|database| {
+        backup.databases.iter().any(|pattern| {
+          pattern == *database
+            || (!SYSTEM_DATABASES.contains(&database.as_str()) && glob_match(pattern, database))
+        })
+      })
+      .cloned()
+      .collect()
+  } else if let Some(database) = default_database {
+    vec![database.to_string()]
+  } else {
+    available
+      .iter()
+      .filter(|database| !SYSTEM_DATABASES.contains(&database.as_str()))
+      .cloned()
+      .collect()
+  };
+
+  databases.retain(|database| {
+    !backup
+      .exclude_databases
+      .iter()
+      .any(|pattern| glob_match(pattern, database))
+  });
+  databases.sort();
+  databases
+}
+
+/// Lists the databases of a backup on the connected server.
+pub async fn resolve_databases(
+  connection: &DatabaseConnection,
+  backup: &Backup,
+) -> Result<Vec<String>, String> {
+  let default_database = connection
+    .default_database()
+    .map_err(|err| err.to_string())?
+    .map(|database| database.name().to_string());
+
+  let available = if backup.databases.is_empty() && default_database.is_some() {
+    Vec::new()
+  } else {
+    connection
+      .list_databases()
+      .await
+      .map_err(|err| format!("Cannot list databases: {}", err))?
+  };
+
+  let databases = select_databases(backup, &available, default_database.as_deref());
+  if databases.is_empty() {
+    return Err("No database matches the backup configuration".to_string());
+  }
+  Ok(databases)
+}
+
+/// A collection rule: a pattern matched against the collection name, or, written as
+/// `<database glob>.<pattern>` like the keys of `collections` and `masking`, against the
+/// collections of the matching databases. Collection names may hold dots, so `cache.*` matches
+/// both the collections of the `cache` database and the collections named `cache.<anything>`;
+/// `<database glob>:<pattern>` only matches the former.
+pub struct CollectionRule {
+  /// Matched against the collection name in every database.
+  name: Option<Pattern>,
+  /// Database glob and collection pattern of qualified rules.
+  qualified: Option<(String, Pattern)>,
+  rule: String,
+}
+
+impl CollectionRule {
+  pub fn parse(rule: &str) -> Result<Self, String> {
+    let qualified = |(database, collection): (&str, &str)| {
+      Pattern::parse(collection).map(|collection| (database.to_string(), collection))
+    };
+    let (name, qualified) = match rule.split_once(':') {
+      _ if rule.starts_with('/') => (Some(Pattern::parse(rule)?), None),
+      Some(parts) => (None, Some(qualified(parts)?)),
+      // Database names hold no dots, the first one ends the database glob
+      None => (
+        Some(Pattern::parse(rule)?),
+        rule.split_once('.').map(qualified).transpose()?,
+      ),
+    };
+
+    Ok(Self {
+      name,
+      qualified,
+      rule: rule.to_string(),
+    })
+  }
+
+  pub fn matches(&self, database: &str, collection: &str) -> bool {
+    self
+      .name
+      .as_ref()
+      .is_some_and(|pattern| pattern.matches(collection))
+      || self
+        .qualified
+        .as_ref()
+        .is_some_and(|(pattern, collection_pattern)| {
+          glob_match(pattern, database) && collection_pattern.matches(collection)
+        })
+  }
+}
+
+/// The `include_collections` and `ignore_collections` rules of a backup.
+pub struct CollectionFilter {
+  include: Vec<CollectionRule>,
+  ignore: Vec<CollectionRule>,
+}
+
+impl CollectionFilter {
+  pub fn new(backup: &Backup) -> Result<Self, String> {
+    let parse = |rules: &[String]| -> Result<Vec<CollectionRule>, String> {
+      rules
+        .iter()
+        .map(|rule| CollectionRule::parse(rule))
+        .collect()
+    };
+
+    Ok(Self {
+      include: parse(&backup.include_collections)?,
+      ignore: parse(&backup.ignore_collections)?,
+    })
+  }
+
+  /// Returns why a collection is skipped, or `None` when it is backed up. Collections matching an
+  /// include rule are always backed up, and only them when there are include rules.
+  pub fn skip_reason(&self, database: &str, collection: &str) -> Option<String> {
+    let matches = |rule: &&CollectionRule| rule.matches(database, collection);
+
+    if !self.include.is_empty() {
+      let included = self.include.iter().any(|rule| matches(&rule));
+      return (!included).then(|| "not matched by include_collections".to_string());
+    }
+
+    self
+      .ignore
+      .iter()
+      .find(matches)
+      .map(|rule| format!("ignore_collections rule \"{}\"", rule.rule))
+  }
+}
+
+/// Returns the setting of a collection in a map keyed by `database.collection` or, for every
+/// database, by collection name.
+pub fn collection_setting<'a, T>(
+  settings: &'a HashMap<String, T>,
+  database: &str,
+  collection: &str,
+) -> Option<&'a T> {
+  settings
+    .get(&format!("{database}.{collection}"))
+    .or_else(|| settings.get(collection))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashMap;
+
+  use bson::doc;
+
+  use crate::{
+    backups::selection::{CollectionFilter, CollectionRule, collection_setting, select_databases},
+    utils::config::{
+      Backup, BackupDatastore, BackupDatastoreType, BackupSchedule, CollectionQuery,
+      DEFAULT_PARALLELISM,
+    },
+  };
+
+  fn sample_backup(databases: &[&str], exclude_databases: &[&str]) -> Backup {
+    Backup {
+      display_name: "Cool Backup".to_string(),
+      connection_string: "mongodb://localhost:27017".to_string(),
+      ignore_collections: vec![
+        "GlobalStats".to_string(),
+        "app:sessions".to_string(),
+        "tmp_2024_*".to_string(),
+        "cache.*".to_string(),
+      ],
+      include_collections: Vec::new(),
+      collections: HashMap::new(),
+      masking: HashMap::new(),
+      masking_secret: None,
+      databases: databases.iter().map(|d| d.to_string()).collect(),
+      exclude_databases: exclude_databases.iter().map(|d| d.to_string()).collect(),
+      datastore: BackupDatastore {
+        storage_type: BackupDatastoreType::FileSystem,
+        path: "/tmp".to_string(),
+      },
+      schedule: BackupSchedule {
+        enabled: false,
+        cron: "0 0 * * *".to_string(),
+      },
+      encryption_key: None,
+      format: Default::default(),
+      json_mode: Default::default(),
+      compression: Default::default(),
+      restore_drill: None,
+      users_and_roles: false,
+      gridfs_objects: false,
+      dedup: false,
+      pause_balancer: false,
+      parallelism: DEFAULT_PARALLELISM,
+      retention: None,
+      read_preference: None,
+      throttle: Default::default(),
+      incremental: None,
+      change_stream: None,
+    }
+  }
+
+  #[test]
+  fn selection_select_databases() {
+    let available: Vec<String> = ["admin", "app", "app_scratch", "billing", "config", "local"]
+      .iter()
+      .map(|d| d.to_string())
+      .collect();
+
+    assert_eq!(
+      select_databases(&sample_backup(&[], &[]), &available, None),
+      vec!["app", "app_scratch", "billing"]
+    );
+    assert_eq!(
+      select_databases(&sample_backup(&[], &["*_scratch"]), &available, None),
+      vec!["app", "billing"]
+    );
+    assert_eq!(
+      select_databases(&sample_backup(&[], &[]), &[], Some("app")),
+      vec!["app"]
+    );
+    assert_eq!(
+      select_databases(&sample_backup(&["*"], &["app*"]), &available, Some("app")),
+      vec!["billing"]
+    );
+    assert_eq!(
+      select_databases(&sample_backup(&["app*", "admin"], &[]), &available, None),
+      vec!["admin", "app", "app_scratch"]
+    );
+  }
+
+  #[test]
+  fn selection_collection_filter() {
+    let mut backup = sample_backup(&[], &[]);
+    let filter = CollectionFilter::new(&backup).unwrap();
+
+    assert_eq!(
+      filter.skip_reason("app", "GlobalStats"),
+      Some("ignore_collections rule \"GlobalStats\"".to_string())
+    );
+    assert!(filter.skip_reason("billing", "GlobalStats").is_some());
+    assert!(filter.skip_reason("app", "sessions").is_some());
+    assert!(filter.skip_reason("billing", "sessions").is_none());
+    assert_eq!(
+      filter.skip_reason("app", "tmp_2024_03"),
+      Some("ignore_collections rule \"tmp_2024_*\"".to_string())
+    );
+    // `cache.*` matches the `cache` database as well as collection names starting with `cache.`
+    assert!(filter.skip_reason("app", "cache.users").is_some());
+    assert!(filter.skip_reason("cache", "users").is_some());
+    assert!(filter.skip_reason("app", "users").is_none());
+
+    backup.ignore_collections = vec![
+      "cache:*".to_string(),
+      "/^tmp:/".to_string(),
+      "app.logs_*".to_string(),
+      "billing./^audit/".to_string(),
+    ];
+    let filter = CollectionFilter::new(&backup).unwrap();
+    assert!(filter.skip_reason("cache", "users").is_some());
+    assert!(filter.skip_reason("app", "cache.users").is_none());
+    assert!(filter.skip_reason("app", "tmp:1").is_some());
+    assert!(filter.skip_reason("app", "logs_2024").is_some());
+    assert!(filter.skip_reason("billing", "logs_2024").is_none());
+    assert!(filter.skip_reason("billing", "audit_trail").is_some());
+    assert!(filter.skip_reason("app", "audit_trail").is_none());
+
+    // Patterns of qualified rules are checked too
+    assert!(CollectionRule::parse("app:/[/").is_err());
+    assert!(CollectionRule::parse("app./[/").is_err());
+
+    backup.include_collections = vec!["app:*".to_string(), "tmp_2024_01".to_string()];
+    let filter = CollectionFilter::new(&backup).unwrap();
+
+    assert!(filter.skip_reason("app", "sessions").is_none());
+    assert!(filter.skip_reason("billing", "tmp_2024_01").is_none());
+    assert!(filter.skip_reason("billing", "app.sessions").is_some());
+    assert_eq!(
+      filter.skip_reason("billing", "users"),
+      Some("not matched by include_collections".to_string())
+    );
+  }
+
+  #[test]
+  fn selection_collection_setting() {
+    let mut backup = sample_backup(&[], &[]);
+    let query = |projection| CollectionQuery {
+      filter: None,
+      projection: Some(doc! { projection: 0 }),
+    };
+    backup.collections = HashMap::from([
+      ("events".to_string(), query("payload")),
+      ("app.events".to_string(), query("user")),
+    ]);
+
+    assert_eq!(
+      collection_setting(&backup.collections, "app", "events"),
+      Some(&query("user"))
+    );
+    assert_eq!(
+      collection_setting(&backup.collections, "billing", "events"),
+      Some(&query("payload"))
+    );
+    assert_eq!(
+      collection_setting(&backup.collections, "app", "users"),
+      None
+    );
+  }
+}
@@ -0,0 +1,420 @@
+use std::time::{Duration, Instant};
+
+use bson::{Bson, RawDocumentBuf};
+use mongodb::change_stream::event::ResumeToken;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  backups::{
+    BackupKind, BackupManifest, BackupObject, CollectionDump, CollectionManifest, ObjectName,
+    OplogPosition, selection,
+  },
+  datastores::{self, Datastore},
+  db::DatabaseConnection,
+  utils::{
+    config::{Backup, BackupChangeStream},
+    logger::Logger,
+  },
+};
+
+/// Pseudo collection holding the change events of a segment.
+const CHANGES_COLLECTION: &str = "$changes";
+
+/// Where the change stream of a backup resumes, stored next to the backup objects.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StreamState {
+  /// The resume token, as canonical Extended JSON.
+  pub resume_token: serde_json::Value,
+}
+
+impl StreamState {
+  pub fn object_name(backup_name: &str) -> String {
+    format!("backup_{backup_name}.stream_state.json")
+  }
+
+  pub fn from_token(token: &ResumeToken) -> Result<Self, String> {
+    let token = bson::serialize_to_bson(token)
+      .map_err(|err| format!("Cannot serialize resume token: {}", err))?;
+
+    Ok(Self {
+      resume_token: token.into_canonical_extjson(),
+    })
+  }
+
+  pub fn token(&self) -> Result<ResumeToken, String> {
+    let token = Bson::try_from(self.resume_token.clone())
+      .map_err(|err| format!("Invalid resume token: {}", err))?;
+
+    bson::deserialize_from_bson(token).map_err(|err| format!("Invalid resume token: {}", err))
+  }
+
+  /// Loads the state of a backup, or `None` when its change stream was never recorded.
+  pub fn load(datastore: &dyn Datastore, backup_name: &str) -> Result<Option<Self>, String> {
+    let object_name = Self::object_name(backup_name);
+    if !datastore.object_exists(&object_name) {
+      return Ok(None);
+    }
+
+    let content = datastore.get_object(object_name)?;
+    serde_json::from_slice(&content)
+      .map(Some)
+      .map_err(|err| format!("Invalid stream state: {}", err))
+  }
+
+  pub fn save(&self, datastore: &dyn Datastore, backup_name: &str) -> Result<(), String> {
+    let content =
+      serde_json::to_vec(self).map_err(|err| format!("Cannot serialize stream state: {}", err))?;
+
+    datastore.replace_object(&Self::object_name(backup_name), &content)
+  }
+}
+
+/// The events of the segment being recorded.
+struct OpenSegment {
+  events: Vec<RawDocumentBuf>,
+  opened_at: Instant,
+}
+
+impl OpenSegment {
+  fn new(now: Instant) -> Self {
+    Self {
+      events: Vec::new(),
+      opened_at: now,
+    }
+  }
+
+  /// Takes the events of the segment once it holds `segment_events` events or stayed open
+  /// `segment_seconds`, possibly none, opening the next one at `now`.
+  fn roll_over(
+    &mut self,
+    settings: &BackupChangeStream,
+    now: Instant,
+  ) -> Option<Vec<RawDocumentBuf>> {
+    let full = self.events.len() as u64 >= settings.segment_events;
+    let expired =
+      now.duration_since(self.opened_at) >= Duration::from_secs(settings.segment_seconds);
+    if !full && !expired {
+      return None;
+    }
+
+    self.opened_at = now;
+    Some(std::mem::take(&mut self.events))
+  }
+}
+
+/// Records the change events of a backup database into segment objects, each stored once it
+/// holds enough events or stayed open long enough.
+pub struct ChangeStreamRecorder<'a> {
+  name: &'a str,
+  backup: &'a Backup,
+}
+
+impl<'a> ChangeStreamRecorder<'a> {
+  pub fn new(name: &'a str, backup: &'a Backup) -> Self {
+    Self { name, backup }
+  }
+
+  /// Records change events until the change stream fails. Segments are stored before the resume
+  /// token is saved, so an interrupted recorder may store some events twice but never loses any.
+  pub async fn run(&self) -> Result<(), String> {
+    let settings = self
+      .backup
+      .change_stream
+      .as_ref()
+      .ok_or(format!("No change stream configured for {}", self.name))?;
+    let datastore = datastores::from_config(&self.backup.datastore)?;
+    let resume_token = StreamState::load(datastore.as_ref(), self.name)?
+      .map(|state| state.token())
+      .transpose()?;
+
+    let mut connection = DatabaseConnection::new();
+    connection
+      .connect(&self.backup.connection_string)
+      .await
+      .map_err(|err| format!("Cannot connect to database: {}", err))?;
+    let res = self
+      .record(&connection, datastore.as_ref(), settings, resume_token)
+      .await;
+    let _ = connection.disconnect().await;
+    res
+  }
+
+  async fn record(
+    &self,
+    connection: &DatabaseConnection,
+    datastore: &(dyn Datastore + Sync),
+    settings: &BackupChangeStream,
+    resume_token: Option<ResumeToken>,
+  ) -> Result<(), String> {
+    let databases = selection::resolve_databases(connection, self.backup).await?;
+    let mut stream = connection
+      .watch(&databases, resume_token)
+      .await
+      .map_err(|err| format!("Cannot open change stream: {}", err))?;
+
+    Logger::info(&format!(
+      "Recording change events of {} from {}",
+      self.backup.display_name,
+      databases.join(", ")
+    ));
+
+    let mut segment = OpenSegment::new(Instant::now());
+    let mut last_timestamp = 0;
+
+    while stream.is_alive() {
+      if let Some(event) = stream
+        .next_if_any()
+        .await
+        .map_err(|err| format!("Cannot read change stream: {}", err))?
+      {
+        segment.events.push(event);
+      }
+
+      if let Some(events) = segment.roll_over(settings, Instant::now()) {
+        if !events.is_empty() {
+          // Segments may be stored faster than once per second
+          last_timestamp = chrono::Local::now().timestamp().max(last_timestamp + 1);
+          let object = self.segment(&databases, events)?;
+          self.store(datastore, &object, last_timestamp)?;
+        }
+        if let Some(token) = stream.resume_token() {
+          StreamState::from_token(&token)?.save(datastore, self.name)?;
+        }
+      }
+    }
+
+    Err("The change stream was invalidated".to_string())
+  }
+
+  /// Builds a segment object, covering the cluster times of its events. Events of a cluster-wide
+  /// change stream are stored under the `admin` database, which such streams are opened on.
+  fn segment(
+    &self,
+    databases: &[String],
+    events: Vec<RawDocumentBuf>,
+  ) -> Result<BackupObject, String> {
+    let database = match databases {
+      [database] => database.as_str(),
+      _ => "admin",
+    };
+    let cluster_time = |event: Option<&RawDocumentBuf>| {
+      event
+        .map(|event| event.get_timestamp("clusterTime"))
+        .transpose()
+        .map(|time| time.map(OplogPosition::from))
+        .map_err(|err| format!("Invalid change event: {}", err))
+    };
+
+    let mut manifest = BackupManifest::new(self.name, &self.backup.display_name);
+    manifest.kind = BackupKind::Changes;
+    manifest.databases = databases.to_vec();
+    manifest.oplog_start = cluster_time(events.first())?;
+    manifest.oplog_end = cluster_time(events.last())?;
+    manifest.collections.push(CollectionManifest {
+      database: database.to_string(),
+      name: CHANGES_COLLECTION.to_string(),
+      document_count: events.len() as u64,
+      indexes: Vec::new(),
+      ..Default::default()
+    });
+
+    Ok(BackupObject {
+      manifest,
+      collections: vec![CollectionDump {
+        database: database.to_string(),
+        name: CHANGES_COLLECTION.to_string(),
+        documents: events,
+      }],
+    })
+  }
+
+  fn store(
+    &self,
+    datastore: &dyn Datastore,
+    object: &BackupObject,
+    timestamp: i64,
+  ) -> Result<(), String> {
+    let object_name = ObjectName {
+      directory: String::new(),
+      backup_name: self.name.to_string(),
+      timestamp,
+      kind: BackupKind::Changes,
+      format: self.backup.format,
+      compression: self.backup.compression,
+      dedup: false,
+    }
+    .to_string();
+    let content = object.encode(
+      self.backup.format,
+      self.backup.json_mode,
+      self.backup.compression,
+      self.backup.encryption_key.as_deref(),
+    )?;
+    datastore.put_object(&object_name, &content)?;
+
+    Logger::info(&format!(
+      "Stored {} change events of {} in {}",
+      object.manifest.document_count(),
+      self.backup.display_name,
+      object_name
+    ));
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+  };
+
+  use bson::{Bson, Timestamp, doc, rawdoc};
+  use mongodb::change_stream::event::ResumeToken;
+
+  use crate::{
+    backups::{
+      BackupKind, OplogPosition,
+      stream::{ChangeStreamRecorder, OpenSegment, StreamState},
+    },
+    datastores::{Datastore, FilesystemDatastore},
+    tests::{clean_test_dir, get_test_dir_path},
+    utils::config::{
+      Backup, BackupChangeStream, BackupDatastore, BackupDatastoreType, BackupSchedule,
+      DEFAULT_PARALLELISM,
+    },
+  };
+
+  fn sample_backup() -> Backup {
+    Backup {
+      display_name: "Cool Backup".to_string(),
+      connection_string: "mongodb://localhost:27017/database".to_string(),
+      ignore_collections: Vec::new(),
+      include_collections: Vec::new(),
+      collections: HashMap::new(),
+      masking: HashMap::new(),
+      masking_secret: None,
+      databases: Vec::new(),
+      exclude_databases: Vec::new(),
+      datastore: BackupDatastore {
+        storage_type: BackupDatastoreType::FileSystem,
+        path: "/tmp".to_string(),
+      },
+      schedule: BackupSchedule {
+        enabled: false,
+        cron: "0 0 * * *".to_string(),
+      },
+      encryption_key: None,
+      format: Default::default(),
+      json_mode: Default::default(),
+      compression: Default::default(),
+      restore_drill: None,
+      users_and_roles: false,
+      gridfs_objects: false,
+      dedup: false,
+      pause_balancer: false,
+      parallelism: DEFAULT_PARALLELISM,
+      retention: None,
+      read_preference: None,
+      throttle: Default::default(),
+      incremental: None,
+      change_stream: None,
+    }
+  }
+
+  #[test]
+  fn stream_state_save_load() {
+    let test_dir_path = get_test_dir_path("stream_state_save_load");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    assert_eq!(StreamState::load(&datastore, "cool").unwrap(), None);
+
+    let token: ResumeToken =
+      bson::deserialize_from_bson(Bson::Document(doc! { "_data": "8263A1B2C3000000012B" }))
+        .unwrap();
+    StreamState::from_token(&token)
+      .unwrap()
+      .save(&datastore, "cool")
+      .unwrap();
+    StreamState::from_token(&token)
+      .unwrap()
+      .save(&datastore, "cool")
+      .unwrap();
+
+    let state = StreamState::load(&datastore, "cool").unwrap().unwrap();
+    assert_eq!(state.token().unwrap(), token);
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn stream_segment() {
+    let backup = sample_backup();
+    let recorder = ChangeStreamRecorder::new("cool", &backup);
+    let ts = |time| Timestamp { time, increment: 1 };
+
+    let object = recorder
+      .segment(
+        &["database".to_string()],
+        vec![
+          rawdoc! { "operationType": "insert", "clusterTime": ts(10) },
+          rawdoc! { "operationType": "delete", "clusterTime": ts(20) },
+        ],
+      )
+      .unwrap();
+
+    assert_eq!(object.manifest.kind, BackupKind::Changes);
+    assert_eq!(
+      object.manifest.oplog_start,
+      Some(OplogPosition::from(ts(10)))
+    );
+    assert_eq!(object.manifest.oplog_end, Some(OplogPosition::from(ts(20))));
+    assert_eq!(object.manifest.document_count(), 2);
+    assert_eq!(object.collections[0].namespace(), "database.$changes");
+    assert!(
+      recorder
+        .segment(&["database".to_string()], vec![rawdoc! {}])
+        .is_err()
+    );
+
+    let databases = vec!["app".to_string(), "billing".to_string()];
+    let object = recorder.segment(&databases, Vec::new()).unwrap();
+    assert_eq!(object.collections[0].namespace(), "admin.$changes");
+    assert_eq!(object.manifest.databases, databases);
+  }
+
+  #[test]
+  fn stream_segment_roll_over() {
+    let settings = BackupChangeStream {
+      enabled: true,
+      segment_events: 3,
+      segment_seconds: 60,
+    };
+    let start = Instant::now();
+    let at = |seconds| start + Duration::from_secs(seconds);
+    let mut segment = OpenSegment::new(start);
+
+    // Segments are stored once they hold enough events
+    segment.events.push(rawdoc! { "n": 1 });
+    segment.events.push(rawdoc! { "n": 2 });
+    assert_eq!(segment.roll_over(&settings, at(10)), None);
+    segment.events.push(rawdoc! { "n": 3 });
+    assert_eq!(segment.roll_over(&settings, at(20)).unwrap().len(), 3);
+
+    // Or once they stayed open long enough, counted from the previous segment
+    segment.events.push(rawdoc! { "n": 4 });
+    assert_eq!(segment.roll_over(&settings, at(79)), None);
+    assert_eq!(
+      segment.roll_over(&settings, at(80)),
+      Some(vec![rawdoc! { "n": 4 }])
+    );
+
+    // Empty segments roll over too, so that the resume token is saved
+    assert_eq!(segment.roll_over(&settings, at(139)), None);
+    assert_eq!(segment.roll_over(&settings, at(140)), Some(Vec::new()));
+    assert!(segment.events.is_empty());
+  }
+}
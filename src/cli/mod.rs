@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
@@ -10,4 +12,68 @@ pub struct Cli {
 #[derive(Subcommand)]
 pub enum Commands {
   Tui,
+  /// Run a configured backup now
+  Backup {
+    name: String,
+    /// Only store the oplog entries written since the previous backup
+    #[arg(long)]
+    incremental: bool,
+  },
+  /// Restore a backup into a MongoDB server
+  Restore {
+    name: String,
+    /// Object to restore, defaults to the latest backup
+    #[arg(long)]
+    object: Option<String>,
+    /// Connection string of the target server, defaults to the backup connection string
+    #[arg(long)]
+    uri: Option<String>,
+    /// Restore every collection into this database, or into `<database>_<name>` databases for
+    /// backups of several databases
+    #[arg(long)]
+    database: Option<String>,
+    /// Drop existing collections before restoring them
+    #[arg(long)]
+    drop: bool,
+    /// Build indexes before loading the documents, slower but unique indexes reject duplicates
+    /// as they are inserted
+    #[arg(long)]
+    indexes_first: bool,
+    /// Restore the users and roles of the backup, skipped when not allowed to
+    #[arg(long)]
+    users_and_roles: bool,
+    /// Unix timestamp to restore to, replaying the incremental backups and change segments
+    /// written before it
+    #[arg(long)]
+    to: Option<i64>,
+    /// Restore the latest sanitized variant, with its masked fields
+    #[arg(long, conflicts_with_all = ["object", "to"])]
+    sanitized: bool,
+  },
+  /// Import a mongodump output directory or archive file into the datastore of a backup
+  Import {
+    name: String,
+    path: PathBuf,
+    /// Unix timestamp to date the backup with, defaults to the dump modification time
+    #[arg(long)]
+    timestamp: Option<i64>,
+  },
+  /// Restore the latest backup into a scratch database and check it against its manifest
+  Drill {
+    name: String,
+  },
+  /// Continuously record the change events of a backup into segment objects
+  Stream {
+    name: String,
+  },
+  /// Delete the objects of a backup older than its latest full backups, and the deduplicated
+  /// chunks and GridFS objects no longer used
+  Prune {
+    name: String,
+    /// Number of full backups to keep, defaults to the configured retention
+    #[arg(long)]
+    keep: Option<usize>,
+  },
+  /// Run scheduled backups, restore drills and enabled change streams
+  Daemon,
 }
@@ -0,0 +1,338 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+  datastores::{self, Datastore, GcLock},
+  utils::{compression, config::BackupCompression, crypto},
+};
+
+/// Extension of the index objects listing the chunks of a deduplicated object.
+pub const INDEX_EXTENSION: &str = ".dedup";
+/// Chunks are stored as `chunks/<first hash byte>/chunk_<hash>`.
+pub const CHUNKS_PREFIX: &str = "chunks/";
+pub const MIN_CHUNK_SIZE: usize = 16 * 1024;
+pub const MAX_CHUNK_SIZE: usize = 256 * 1024;
+/// Cuts where the 16 high bits of the hash are zero, for chunks of about 64 KiB past the minimum
+/// size. High bits depend on the last 64 bytes, low bits on fewer.
+const CUT_MASK: u64 = 0xffff << 48;
+const GEAR: [u64; 256] = gear_table();
+
+/// Random values of the bytes for the rolling hash, from a fixed seed so that cuts stay the same
+/// across versions.
+const fn gear_table() -> [u64; 256] {
+  let mut table = [0; 256];
+  let mut state: u64 = 0;
+  let mut i = 0;
+  while i < table.len() {
+    state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut value = state;
+    value = (value ^ (value >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94d049bb133111eb);
+    table[i] = value ^ (value >> 31);
+    i += 1;
+  }
+  table
+}
+
+/// The chunks of a deduplicated object, stored under its name.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct DedupIndex {
+  pub gzip: bool,
+  pub encrypted: bool,
+  pub size: u64,
+  pub chunks: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct DedupStats {
+  pub chunks: usize,
+  /// Chunks not stored by a previous object.
+  pub stored: usize,
+}
+
+/// Stores objects as content-defined chunks on top of a datastore, each chunk being stored once
+/// whatever the number of objects holding it. Chunks are compressed and encrypted one by one, so
+/// that unchanged content gives the same chunks from one backup to the next.
+pub struct DedupStore<'a> {
+  datastore: &'a dyn Datastore,
+  compression: BackupCompression,
+  encryption_key: Option<&'a str>,
+}
+
+impl<'a> DedupStore<'a> {
+  pub fn new(
+    datastore: &'a dyn Datastore,
+    compression: BackupCompression,
+    encryption_key: Option<&'a str>,
+  ) -> Self {
+    Self {
+      datastore,
+      compression,
+      encryption_key,
+    }
+  }
+
+  /// Stores the chunks of `content` missing from the datastore, then its index under
+  /// `object_name`. A `WriteLease` must be held meanwhile, so that garbage collection does not
+  /// delete the chunks stored by previous objects before the index refers to them.
+  pub fn put(&self, object_name: &str, content: &[u8]) -> Result<DedupStats, String> {
+    let gzip = self.compression == BackupCompression::Gzip;
+    let mut index = DedupIndex {
+      gzip,
+      encrypted: self.encryption_key.is_some(),
+      size: content.len() as u64,
+      chunks: Vec::new(),
+    };
+    let mut stored = 0;
+
+    let mut start = 0;
+    for end in chunk_ends(content) {
+      let chunk = &content[start..end];
+      start = end;
+
+      let hash = chunk_hash(chunk, gzip, self.encryption_key);
+      let chunk = match self.compression {
+        BackupCompression::None => chunk.to_vec(),
+        BackupCompression::Gzip => compression::gzip(chunk)?,
+      };
+      let chunk = match self.encryption_key {
+        Some(key) => crypto::encrypt(key, &chunk)?,
+        None => chunk,
+      };
+      if datastores::put_shared_object(self.datastore, &chunk_name(&hash), &chunk)? {
+        stored += 1;
+      }
+      index.chunks.push(hash);
+    }
+
+    let stats = DedupStats {
+      chunks: index.chunks.len(),
+      stored,
+    };
+    let index =
+      serde_json::to_vec(&index).map_err(|err| format!("Cannot serialize index: {}", err))?;
+    self.datastore.put_object(object_name, &index)?;
+    Ok(stats)
+  }
+
+  /// Reassembles an object from its index, checking every chunk against its hash.
+  pub fn get(&self, object_name: &str) -> Result<Vec<u8>, String> {
+    let index = load_index(self.datastore, object_name)?;
+    if index.encrypted && self.encryption_key.is_none() {
+      return Err(format!(
+        "{object_name} is encrypted, an encryption key is required"
+      ));
+    }
+
+    let encryption_key = self.encryption_key.filter(|_| index.encrypted);
+
+    let mut content = Vec::with_capacity(index.size as usize);
+    for hash in &index.chunks {
+      let chunk = self.datastore.get_object(chunk_name(hash))?;
+      let chunk = match encryption_key {
+        Some(key) => crypto::decrypt(key, &chunk)?,
+        None => chunk,
+      };
+      let chunk = match index.gzip {
+        true => compression::gunzip(&chunk)?,
+        false => chunk,
+      };
+      if chunk_hash(&chunk, index.gzip, encryption_key) != *hash {
+        return Err(format!("Chunk {hash} of {object_name} is corrupted"));
+      }
+      content.extend_from_slice(&chunk);
+    }
+
+    if content.len() as u64 != index.size {
+      return Err(format!("{object_name} is incomplete"));
+    }
+    Ok(content)
+  }
+
+  /// Deletes the chunks no index of the datastore refers to, returning their number. Fails while
+  /// objects are being stored, as their chunks are written before their index.
+  pub fn collect_garbage(datastore: &dyn Datastore) -> Result<usize, String> {
+    let mut lock = GcLock::acquire(datastore)?;
+    let objects = datastore.list_objects()?;
+
+    let mut referenced = HashSet::new();
+    for object in objects
+      .iter()
+      .filter(|object| object.ends_with(INDEX_EXTENSION))
+    {
+      referenced.extend(
+        load_index(datastore, object)?
+          .chunks
+          .into_iter()
+          .map(chunk_name),
+      );
+      lock.refresh()?;
+    }
+
+    let mut deleted = 0;
+    for chunk in objects
+      .iter()
+      .filter(|object| object.starts_with(CHUNKS_PREFIX) && !referenced.contains(*object))
+    {
+      datastore.delete_object(chunk)?;
+      deleted += 1;
+      lock.refresh()?;
+    }
+    Ok(deleted)
+  }
+}
+
+fn load_index(datastore: &dyn Datastore, object_name: &str) -> Result<DedupIndex, String> {
+  let content = datastore.get_object(object_name.to_string())?;
+  serde_json::from_slice(&content).map_err(|err| format!("Invalid index {}: {}", object_name, err))
+}
+
+/// Hashes a chunk along with how it is stored, so that chunks written with another compression
+/// or key are not reused. Encrypted chunks are named by an HMAC, not revealing their content.
+fn chunk_hash(chunk: &[u8], gzip: bool, encryption_key: Option<&str>) -> String {
+  let content = [&[gzip as u8], chunk].concat();
+  match encryption_key {
+    Some(key) => crypto::hmac(key, &content),
+    None => Sha256::digest(&content)
+      .iter()
+      .map(|byte| format!("{byte:02x}"))
+      .collect(),
+  }
+}
+
+fn chunk_name(hash: impl AsRef<str>) -> String {
+  let hash = hash.as_ref();
+  format!("{CHUNKS_PREFIX}{}/chunk_{hash}", &hash[..2])
+}
+
+/// Splits content where a rolling hash of its last bytes matches, so that an insertion or a
+/// deletion only changes the chunks around it. Returns the end offset of every chunk.
+pub fn chunk_ends(content: &[u8]) -> Vec<usize> {
+  let mut ends = Vec::new();
+  let mut start = 0;
+
+  while start < content.len() {
+    let max_end = (start + MAX_CHUNK_SIZE).min(content.len());
+    let mut end = max_end;
+    let mut hash: u64 = 0;
+    for (i, byte) in content[start..max_end].iter().enumerate() {
+      hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+      if i + 1 >= MIN_CHUNK_SIZE && hash & CUT_MASK == 0 {
+        end = start + i + 1;
+        break;
+      }
+    }
+
+    ends.push(end);
+    start = end;
+  }
+  ends
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::HashSet;
+
+  use crate::{
+    datastores::{
+      Datastore, FilesystemDatastore,
+      dedup::{DedupStore, MAX_CHUNK_SIZE, MIN_CHUNK_SIZE, chunk_ends},
+    },
+    tests::{clean_test_dir, get_test_dir_path},
+    utils::{config::BackupCompression, crypto::generate_key},
+  };
+
+  fn random_content(size: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..size)
+      .map(|_| {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+      })
+      .collect()
+  }
+
+  fn chunks(content: &[u8]) -> Vec<&[u8]> {
+    let mut start = 0;
+    chunk_ends(content)
+      .into_iter()
+      .map(|end| {
+        let chunk = &content[start..end];
+        start = end;
+        chunk
+      })
+      .collect()
+  }
+
+  #[test]
+  fn dedup_chunk_ends() {
+    assert!(chunk_ends(&[]).is_empty());
+    assert_eq!(chunk_ends(b"small"), vec![5]);
+
+    let content = random_content(4 * 1024 * 1024, 42);
+    let original = chunks(&content);
+    assert!(original.len() > 4 * 1024 * 1024 / MAX_CHUNK_SIZE);
+    assert!(
+      original[..original.len() - 1]
+        .iter()
+        .all(|chunk| (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk.len()))
+    );
+
+    // An insertion only changes the chunks around it
+    let mut edited = content.clone();
+    edited.splice(2_000_000..2_000_000, b"inserted".iter().copied());
+    let original: HashSet<&[u8]> = original.into_iter().collect();
+    let changed = chunks(&edited)
+      .into_iter()
+      .filter(|chunk| !original.contains(chunk))
+      .count();
+    assert!((1..=2).contains(&changed));
+  }
+
+  #[test]
+  fn dedup_store() {
+    let test_dir_path = get_test_dir_path("dedup_store");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+    let key = generate_key();
+    let store = DedupStore::new(&datastore, BackupCompression::Gzip, Some(&key));
+
+    let first = random_content(2 * 1024 * 1024, 7);
+    let stats = store.put("backup_cool_1.bson.gz.dedup", &first).unwrap();
+    assert_eq!(stats.stored, stats.chunks);
+    assert_eq!(store.get("backup_cool_1.bson.gz.dedup").unwrap(), first);
+
+    let mut second = first.clone();
+    second[1_000_000] ^= 0xff;
+    let stats = store.put("backup_cool_2.bson.gz.dedup", &second).unwrap();
+    assert!((1..=2).contains(&stats.stored));
+    assert_eq!(store.get("backup_cool_2.bson.gz.dedup").unwrap(), second);
+
+    // Chunks are only readable with their key
+    let unkeyed = DedupStore::new(&datastore, BackupCompression::Gzip, None);
+    assert!(unkeyed.get("backup_cool_2.bson.gz.dedup").is_err());
+    let other_key = generate_key();
+    let other = DedupStore::new(&datastore, BackupCompression::Gzip, Some(&other_key));
+    assert!(other.get("backup_cool_2.bson.gz.dedup").is_err());
+
+    assert_eq!(DedupStore::collect_garbage(&datastore).unwrap(), 0);
+    datastore
+      .delete_object("backup_cool_1.bson.gz.dedup")
+      .unwrap();
+    let deleted = DedupStore::collect_garbage(&datastore).unwrap();
+    assert!((1..=2).contains(&deleted));
+    assert_eq!(store.get("backup_cool_2.bson.gz.dedup").unwrap(), second);
+
+    datastore
+      .delete_object("backup_cool_2.bson.gz.dedup")
+      .unwrap();
+    DedupStore::collect_garbage(&datastore).unwrap();
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    clean_test_dir(test_dir_path);
+  }
+}
@@ -1,15 +1,34 @@
 use std::{
-  fs::{File, create_dir_all, read_dir, remove_file},
+  fs::{DirBuilder, File, OpenOptions, hard_link, read_dir, remove_dir, remove_file, rename},
   io::{ErrorKind, Read, Write},
-  path::{Path, PathBuf},
-  sync::OnceLock,
+  path::{Component, Path, PathBuf},
+  process,
+  sync::{
+    OnceLock,
+    atomic::{AtomicU64, Ordering},
+  },
+  time::{Duration, SystemTime},
 };
 
+#[cfg(unix)]
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+
 use regex::Regex;
 
 use crate::datastores::Datastore;
 
 static BACKUP_FILE_REGEX: OnceLock<Regex> = OnceLock::new();
+static TEMP_FILE_REGEX: OnceLock<Regex> = OnceLock::new();
+/// Extension of the files objects are written to before being renamed into place.
+const TEMP_FILE_EXTENSION: &str = ".tmp";
+/// Numbers the temporary files of the process, so that concurrent writes never share one.
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+/// Age after which a temporary file is left over by a writer, even if its process still runs.
+const TEMP_FILE_STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+#[cfg(unix)]
+const FILE_MODE: u32 = 0o600;
+#[cfg(unix)]
+const DIR_MODE: u32 = 0o700;
 
 pub struct FilesystemDatastore {
   base_path: PathBuf,
@@ -22,7 +41,7 @@ impl Datastore for FilesystemDatastore {
     };
 
     if !instance.base_path.exists() {
-      let _ = create_dir_all(instance.base_path.clone())
+      let _ = Self::create_dirs(&instance.base_path)
         .map_err(|err| panic!("Cannot create datastore base directory: {}", err));
     }
 
@@ -30,59 +49,75 @@ impl Datastore for FilesystemDatastore {
       panic!("Datastore is not a directory")
     }
 
+    // Writers which crashed leave their temporary files behind
+    Self::sweep_temp_files(&instance.base_path);
+
     instance
   }
 
-  fn get_object(&self, path: String) -> Result<String, String> {
-    let full_path = self.base_path.join(Path::new(path.as_str()));
+  fn get_object(&self, path: String) -> Result<Vec<u8>, String> {
+    let full_path = self.object_path(&path)?;
 
     let mut file = File::open(full_path.display().to_string())
       .map_err(|err| format!("Couldn't open file {}: {}", full_path.display(), err))?;
 
-    let mut content = String::new();
+    let mut content = Vec::new();
     file
-      .read_to_string(&mut content)
+      .read_to_end(&mut content)
       .map_err(|err| format!("Couldn't read file {}: {}", full_path.display(), err))?;
 
     Ok(content)
   }
 
   fn list_objects(&self) -> Result<Vec<String>, String> {
-    let backup_file_regex = BACKUP_FILE_REGEX
-      .get_or_init(|| Regex::new(r"^backup_\w+_[0-9]+\.json$").expect("invalid regex"));
-    let dir_content = read_dir(self.base_path.clone())
-      .map_err(|err| format!("Cannot read read datastore directory content: {}", err))?
-      .filter_map(Result::ok)
-      .filter_map(|entry| {
-        let name = entry.file_name();
-        let name = name.to_str()?;
-        backup_file_regex.is_match(name).then(|| name.to_string())
-      })
-      .map(|f| f)
-      .collect();
+    self.list_objects_with_prefix("")
+  }
+
+  fn list_objects_with_prefix(&self, prefix: &str) -> Result<Vec<String>, String> {
+    // Only the directory holding the prefix needs to be walked
+    let directory = match prefix.rfind('/') {
+      Some(end) => &prefix[..end],
+      None => "",
+    };
+    let directory_path = match directory {
+      "" => self.base_path.clone(),
+      _ => self.object_path(directory)?,
+    };
+    if !directory_path.is_dir() {
+      return Ok(Vec::new());
+    }
 
-    Ok(dir_content)
+    let mut objects = Vec::new();
+    Self::walk(&directory_path, directory, &mut objects)?;
+    objects.retain(|object: &String| object.starts_with(prefix));
+    objects.sort();
+
+    Ok(objects)
+  }
+
+  fn object_exists(&self, object_name: &str) -> bool {
+    self
+      .object_path(object_name)
+      .is_ok_and(|file_path| file_path.is_file())
   }
 
   fn put_object(&self, object_name: &str, obj_content: &[u8]) -> Result<(), String> {
-    let file_path = self.base_path.join(Path::new(object_name));
+    let file_path = self.object_path(object_name)?;
 
     if file_path.exists() {
       return Err(format!("File {} already exists", file_path.display()));
     }
 
-    let mut file = File::create(file_path.clone())
-      .map_err(|e| format!("Cannot create file {}: {}", file_path.display(), e))?;
-
-    file
-      .write_all(obj_content)
-      .map_err(|e| format!("Cannot write file {}: {}", file_path.display(), e))?;
+    self.write_atomic(&file_path, obj_content, false)
+  }
 
-    Ok(())
+  fn replace_object(&self, object_name: &str, obj_content: &[u8]) -> Result<(), String> {
+    let file_path = self.object_path(object_name)?;
+    self.write_atomic(&file_path, obj_content, true)
   }
 
   fn delete_object(&self, object_name: &str) -> Result<(), String> {
-    let file_path = self.base_path.join(Path::new(object_name));
+    let file_path = self.object_path(object_name)?;
 
     let _ = remove_file(file_path.clone()).map_err(|e| {
       if e.kind() == ErrorKind::NotFound {
@@ -92,13 +127,218 @@ impl Datastore for FilesystemDatastore {
       }
     })?;
 
+    // Directories left empty are removed, up to the base directory
+    for dir in file_path.ancestors().skip(1) {
+      if dir == self.base_path || remove_dir(dir).is_err() {
+        break;
+      }
+    }
+
+    Ok(())
+  }
+}
+
+impl FilesystemDatastore {
+  /// Resolves an object name, made of `/` separated directories and a file name, within the base
+  /// directory. Names leaving it, through `..`, an absolute path or a symlink, are rejected.
+  fn object_path(&self, object_name: &str) -> Result<PathBuf, String> {
+    let path = Path::new(object_name);
+    let is_relative = path
+      .components()
+      .all(|component| matches!(component, Component::Normal(_)));
+    if object_name.is_empty() || !is_relative {
+      return Err(format!("Invalid object name {}", object_name));
+    }
+
+    let mut full_path = self.base_path.clone();
+    for component in path.components() {
+      full_path.push(component);
+      let is_symlink = full_path
+        .symlink_metadata()
+        .is_ok_and(|metadata| metadata.file_type().is_symlink());
+      if is_symlink && !self.contains(&full_path)? {
+        return Err(format!(
+          "Object {} leads out of the datastore through a symlink",
+          object_name
+        ));
+      }
+    }
+
+    Ok(full_path)
+  }
+
+  /// Tells whether a path resolves within the base directory.
+  fn contains(&self, path: &Path) -> Result<bool, String> {
+    let base_path = self
+      .base_path
+      .canonicalize()
+      .map_err(|err| format!("Cannot resolve {}: {}", self.base_path.display(), err))?;
+    let path = path
+      .canonicalize()
+      .map_err(|err| format!("Cannot resolve {}: {}", path.display(), err))?;
+
+    Ok(path.starts_with(base_path))
+  }
+
+  /// Creates a directory and its parents, only accessible by the owner as they hold database
+  /// data.
+  fn create_dirs(path: &Path) -> std::io::Result<()> {
+    let mut builder = DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(DIR_MODE);
+    builder.create(path)
+  }
+
+  /// Adds the backup objects, chunks, GridFS objects and leases of a directory and its
+  /// subdirectories, named from `directory`.
+  fn walk(path: &Path, directory: &str, objects: &mut Vec<String>) -> Result<(), String> {
+    let backup_file_regex = BACKUP_FILE_REGEX.get_or_init(|| {
+      Regex::new(
+        r"^(backup_\w+_[0-9]+(\.oplog|\.changes|\.sanitized)?\.(json|archive|bson)(\.gz)?(\.dedup)?(\.gridfs)?|(chunk|gridfs)_[0-9a-f]{64}|lease_[0-9]+_[0-9]+)$",
+      )
+        .expect("invalid regex")
+    });
+    let entries = read_dir(path)
+      .map_err(|err| format!("Cannot read read datastore directory content: {}", err))?
+      .filter_map(Result::ok);
+
+    for entry in entries {
+      let Some(name) = entry.file_name().to_str().map(str::to_string) else {
+        continue;
+      };
+      let object_name = match directory {
+        "" => name.clone(),
+        _ => format!("{directory}/{name}"),
+      };
+
+      match entry.file_type() {
+        Ok(file_type) if file_type.is_dir() => Self::walk(&entry.path(), &object_name, objects)?,
+        Ok(file_type) if file_type.is_file() && backup_file_regex.is_match(&name) => {
+          objects.push(object_name)
+        }
+        _ => {}
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Removes the temporary files of a directory and its subdirectories whose writer is no longer
+  /// running or which are older than `TEMP_FILE_STALE_AFTER`.
+  fn sweep_temp_files(path: &Path) {
+    let temp_file_regex = TEMP_FILE_REGEX
+      .get_or_init(|| Regex::new(r"^\..+\.([0-9]+)\.[0-9]+\.tmp$").expect("invalid regex"));
+    let Ok(entries) = read_dir(path) else {
+      return;
+    };
+
+    for entry in entries.filter_map(Result::ok) {
+      let Ok(metadata) = entry.metadata() else {
+        continue;
+      };
+      if metadata.is_dir() {
+        Self::sweep_temp_files(&entry.path());
+        continue;
+      }
+
+      let name = entry.file_name().to_string_lossy().to_string();
+      let Some(pid) = temp_file_regex
+        .captures(&name)
+        .and_then(|captures| captures[1].parse::<u32>().ok())
+      else {
+        continue;
+      };
+      let is_old = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+        .is_some_and(|age| age > TEMP_FILE_STALE_AFTER);
+      if is_old || !Self::is_running(pid) {
+        let _ = remove_file(entry.path());
+      }
+    }
+  }
+
+  /// Tells whether a process runs. Without a way to know, it is assumed to.
+  fn is_running(pid: u32) -> bool {
+    if pid == process::id() {
+      return true;
+    }
+    let proc_path = Path::new("/proc");
+    !proc_path.is_dir() || proc_path.join(pid.to_string()).exists()
+  }
+
+  /// Writes a file through a temporary file of the same directory moved into place, so that a
+  /// crash never leaves a truncated file under the final name. Unless `replace` is set, the file
+  /// is linked into place, which fails rather than overwriting a file written meanwhile.
+  fn write_atomic(&self, file_path: &Path, content: &[u8], replace: bool) -> Result<(), String> {
+    let file_name = file_path
+      .file_name()
+      .ok_or(format!("Invalid file name {}", file_path.display()))?;
+    let temp_path = file_path.with_file_name(format!(
+      ".{}.{}.{}{}",
+      file_name.to_string_lossy(),
+      process::id(),
+      TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed),
+      TEMP_FILE_EXTENSION
+    ));
+
+    if let Some(dir) = file_path.parent() {
+      Self::create_dirs(dir)
+        .map_err(|e| format!("Cannot create directory {}: {}", dir.display(), e))?;
+    }
+
+    let written = Self::write_synced(&temp_path, content);
+    let res = written.and_then(|()| match replace {
+      true => rename(&temp_path, file_path)
+        .map_err(|e| format!("Cannot rename file {}: {}", temp_path.display(), e)),
+      false => hard_link(&temp_path, file_path).map_err(|e| match e.kind() {
+        ErrorKind::AlreadyExists => format!("File {} already exists", file_path.display()),
+        _ => format!("Cannot link file {}: {}", temp_path.display(), e),
+      }),
+    });
+    // Gone once renamed, and no longer needed once linked
+    let _ = remove_file(&temp_path);
+    res?;
+
+    // The rename is only durable once the directory entry is
+    #[cfg(unix)]
+    if let Some(dir) = file_path.parent() {
+      File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("Cannot sync directory {}: {}", dir.display(), e))?;
+    }
+
     Ok(())
   }
+
+  /// Writes a new file, only accessible by the owner. Files are created exclusively, so a symlink
+  /// planted at their path is never followed.
+  fn write_synced(file_path: &Path, content: &[u8]) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(FILE_MODE);
+    let mut file = options
+      .open(file_path)
+      .map_err(|e| format!("Cannot create file {}: {}", file_path.display(), e))?;
+
+    file
+      .write_all(content)
+      .and_then(|()| file.sync_all())
+      .map_err(|e| format!("Cannot write file {}: {}", file_path.display(), e))
+  }
 }
 
 #[cfg(test)]
 mod tests {
-  use std::fs::{create_dir_all, write};
+  use std::{
+    fs::{File, create_dir_all, read_dir, write},
+    path::Path,
+    process, thread,
+    time::{Duration, SystemTime},
+  };
 
   use chrono::Timelike;
 
@@ -169,6 +409,120 @@ mod tests {
     clean_test_dir(test_dir_path);
   }
 
+  #[test]
+  fn fs_datastore_put_object_atomically() {
+    let test_dir_path = get_test_dir_path("fs_datastore_put_object_atomically");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    datastore
+      .put_object("backup_cool_1.json", b"complete")
+      .unwrap();
+    let res = datastore.put_object("backup_cool_1.json", b"other");
+    assert!(res.is_err());
+
+    let files: Vec<String> = read_dir(&test_dir_path)
+      .unwrap()
+      .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+      .collect();
+    assert_eq!(files, vec!["backup_cool_1.json".to_string()]);
+    assert_eq!(
+      datastore
+        .get_object("backup_cool_1.json".to_string())
+        .unwrap(),
+      b"complete"
+    );
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn fs_datastore_concurrent_writes() {
+    let test_dir_path = get_test_dir_path("fs_datastore_concurrent_writes");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+    let contents: Vec<Vec<u8>> = (0..8u8).map(|i| vec![i; 64 * 1024]).collect();
+
+    // Writers of the same object each use their own temporary file, only one of them creates it
+    let created = thread::scope(|scope| {
+      let writers: Vec<_> = contents
+        .iter()
+        .map(|content| scope.spawn(|| datastore.put_object("backup_cool_1.json", content)))
+        .collect();
+      writers
+        .into_iter()
+        .map(|writer| writer.join().unwrap())
+        .filter(Result::is_ok)
+        .count()
+    });
+    assert_eq!(created, 1);
+    let content = datastore
+      .get_object("backup_cool_1.json".to_string())
+      .unwrap();
+    assert!(contents.contains(&content));
+
+    thread::scope(|scope| {
+      for content in &contents {
+        let datastore = &datastore;
+        scope.spawn(move || datastore.replace_object("state.json", content).unwrap());
+      }
+    });
+    let content = datastore.get_object("state.json".to_string()).unwrap();
+    assert!(contents.contains(&content));
+    assert_eq!(read_dir(&test_dir_path).unwrap().count(), 2);
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn fs_datastore_sweep_temp_files() {
+    let test_dir_path = get_test_dir_path("fs_datastore_sweep_temp_files");
+    clean_test_dir(test_dir_path.clone());
+    create_dir_all(format!("{test_dir_path}/cool")).unwrap();
+    let pid = process::id();
+
+    // Beyond the highest pid Linux hands out, so never running
+    let dead = format!("{test_dir_path}/cool/.backup_cool_1.json.4194305.0.tmp");
+    let running = format!("{test_dir_path}/.state.json.{pid}.0.tmp");
+    let old = format!("{test_dir_path}/.state.json.{pid}.1.tmp");
+    for path in [&dead, &running, &old] {
+      write(path, b"partial").unwrap();
+    }
+    File::options()
+      .write(true)
+      .open(&old)
+      .unwrap()
+      .set_modified(SystemTime::now() - Duration::from_secs(2 * 60 * 60))
+      .unwrap();
+
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    assert!(!Path::new(&dead).exists());
+    assert!(!Path::new(&old).exists());
+    assert!(Path::new(&running).exists());
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn fs_datastore_replace_object() {
+    let test_dir_path = get_test_dir_path("fs_datastore_replace_object");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    datastore.replace_object("state.json", b"first").unwrap();
+    datastore.replace_object("state.json", b"second").unwrap();
+
+    assert_eq!(
+      datastore.get_object("state.json".to_string()).unwrap(),
+      b"second"
+    );
+    assert_eq!(read_dir(&test_dir_path).unwrap().count(), 1);
+
+    clean_test_dir(test_dir_path);
+  }
+
   #[test]
   fn fs_datastore_get_object() {
     let test_dir_path = get_test_dir_path("fs_datastore_get_object");
@@ -181,7 +535,23 @@ mod tests {
     assert!(res.is_ok());
     let res = res.unwrap();
 
-    assert_eq!(res, "This is the best test :)");
+    assert_eq!(res, b"This is the best test :)");
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn fs_datastore_object_exists() {
+    let test_dir_path = get_test_dir_path("fs_datastore_object_exists");
+    clean_test_dir(test_dir_path.clone());
+
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+    let _ = datastore.put_object("test.txt", &[4]);
+    let _ = create_dir_all(format!("{test_dir_path}/test_dir"));
+
+    assert!(datastore.object_exists("test.txt"));
+    assert!(!datastore.object_exists("unknown.txt"));
+    assert!(!datastore.object_exists("test_dir"));
 
     clean_test_dir(test_dir_path);
   }
@@ -258,6 +628,175 @@ mod tests {
     clean_test_dir(test_dir_path);
   }
 
+  #[test]
+  fn fs_datastore_nested_objects() {
+    let test_dir_path = get_test_dir_path("fs_datastore_nested_objects");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    for object_name in [
+      "backup_cool_1.json",
+      "cool/2026/10/backup_cool_2.json",
+      "cool/2026/11/backup_cool_3.json.gz",
+      "cool/2026/11/notes.txt",
+      "other/backup_other_4.bson",
+    ] {
+      datastore.put_object(object_name, b"test").unwrap();
+    }
+
+    assert!(datastore.object_exists("cool/2026/10/backup_cool_2.json"));
+    assert_eq!(
+      datastore
+        .get_object("cool/2026/10/backup_cool_2.json".to_string())
+        .unwrap(),
+      b"test"
+    );
+    assert_eq!(
+      datastore.list_objects().unwrap(),
+      vec![
+        "backup_cool_1.json",
+        "cool/2026/10/backup_cool_2.json",
+        "cool/2026/11/backup_cool_3.json.gz",
+        "other/backup_other_4.bson",
+      ]
+    );
+    assert_eq!(
+      datastore.list_objects_with_prefix("cool/2026/1").unwrap(),
+      vec![
+        "cool/2026/10/backup_cool_2.json",
+        "cool/2026/11/backup_cool_3.json.gz",
+      ]
+    );
+    assert_eq!(
+      datastore.list_objects_with_prefix("backup_").unwrap(),
+      vec!["backup_cool_1.json"]
+    );
+    assert!(
+      datastore
+        .list_objects_with_prefix("missing/")
+        .unwrap()
+        .is_empty()
+    );
+
+    // Directories left empty by a deletion are removed
+    datastore
+      .delete_object("cool/2026/10/backup_cool_2.json")
+      .unwrap();
+    assert!(!Path::new(&format!("{test_dir_path}/cool/2026/10")).exists());
+    assert!(Path::new(&format!("{test_dir_path}/cool/2026/11")).exists());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[test]
+  fn fs_datastore_path_traversal() {
+    let test_dir_path = get_test_dir_path("fs_datastore_path_traversal");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(format!("{test_dir_path}/store").as_str());
+
+    for object_name in [
+      "../backup_cool_1.json",
+      "cool/../../backup_cool_1.json",
+      "./backup_cool_1.json",
+      "/tmp/backup_cool_1.json",
+      "",
+    ] {
+      assert!(datastore.put_object(object_name, b"test").is_err());
+      assert!(datastore.replace_object(object_name, b"test").is_err());
+      assert!(datastore.get_object(object_name.to_string()).is_err());
+      assert!(datastore.delete_object(object_name).is_err());
+      assert!(!datastore.object_exists(object_name));
+    }
+    assert!(datastore.list_objects_with_prefix("../").is_err());
+    assert!(!Path::new(&format!("{test_dir_path}/backup_cool_1.json")).exists());
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn fs_datastore_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let test_dir_path = get_test_dir_path("fs_datastore_symlinks");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(format!("{test_dir_path}/store").as_str());
+    create_dir_all(format!("{test_dir_path}/outside")).unwrap();
+    write(
+      format!("{test_dir_path}/outside/backup_cool_1.json"),
+      b"secret",
+    )
+    .unwrap();
+    create_dir_all(format!("{test_dir_path}/store/inside")).unwrap();
+
+    symlink(
+      format!("{test_dir_path}/outside"),
+      format!("{test_dir_path}/store/escape"),
+    )
+    .unwrap();
+    symlink(
+      format!("{test_dir_path}/store/inside"),
+      format!("{test_dir_path}/store/alias"),
+    )
+    .unwrap();
+
+    assert!(
+      datastore
+        .get_object("escape/backup_cool_1.json".to_string())
+        .is_err()
+    );
+    assert!(
+      datastore
+        .put_object("escape/backup_cool_2.json", b"test")
+        .is_err()
+    );
+    assert!(
+      datastore
+        .delete_object("escape/backup_cool_1.json")
+        .is_err()
+    );
+    assert!(!datastore.object_exists("escape/backup_cool_1.json"));
+    assert!(!Path::new(&format!("{test_dir_path}/outside/backup_cool_2.json")).exists());
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    // Symlinks staying within the datastore are followed
+    datastore
+      .put_object("alias/backup_cool_3.json", b"test")
+      .unwrap();
+    assert!(datastore.object_exists("inside/backup_cool_3.json"));
+
+    clean_test_dir(test_dir_path);
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn fs_datastore_permissions() {
+    use std::{fs::metadata, os::unix::fs::PermissionsExt};
+
+    let test_dir_path = get_test_dir_path("fs_datastore_permissions");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = FilesystemDatastore::new(test_dir_path.as_str());
+
+    datastore
+      .put_object("cool/backup_cool_1.json", b"test")
+      .unwrap();
+    datastore.replace_object("state.json", b"test").unwrap();
+
+    let mode = |path: &str| {
+      metadata(format!("{test_dir_path}/{path}"))
+        .unwrap()
+        .permissions()
+        .mode()
+        & 0o777
+    };
+    assert_eq!(mode(""), 0o700);
+    assert_eq!(mode("cool"), 0o700);
+    assert_eq!(mode("cool/backup_cool_1.json"), 0o600);
+    assert_eq!(mode("state.json"), 0o600);
+
+    clean_test_dir(test_dir_path);
+  }
+
   #[test]
   fn fs_datastore_delete_object() {
     let test_dir_path = get_test_dir_path("fs_datastore_delete_object");
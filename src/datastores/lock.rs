@@ -0,0 +1,252 @@
+use std::{
+  process,
+  sync::{
+    Arc, Mutex,
+    atomic::{AtomicU64, Ordering},
+  },
+  time::Duration,
+};
+
+use tokio::task::JoinHandle;
+
+use crate::{datastores::Datastore, utils::logger::Logger};
+
+/// Leases are stored as `locks/lease_<pid>_<number>`, the garbage collection lock as
+/// `locks/gc.lock`, both holding the Unix timestamp they were last refreshed at, followed by the
+/// token of their owner.
+const LEASES_PREFIX: &str = "locks/lease_";
+const GC_LOCK: &str = "locks/gc.lock";
+/// Leases and locks not refreshed for that many seconds were left by a crashed process.
+const STALE_AFTER: i64 = 10 * 60;
+const REFRESH_AFTER: i64 = 60;
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Numbers the leases and locks of the process, so that concurrent owners never share one.
+static TOKEN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Held while storing objects which refer to shared objects, like deduplicated chunks, so that
+/// garbage collection never deletes what they are about to refer to. Refreshed in the background
+/// for as long as it is held, and released when dropped.
+pub struct WriteLease {
+  datastore: Arc<dyn Datastore + Send + Sync>,
+  /// Name of the lease object, taken on release so that no refresh writes it again.
+  name: Arc<Mutex<Option<String>>>,
+  refresher: JoinHandle<()>,
+}
+
+impl WriteLease {
+  /// Takes a lease, waiting for a running garbage collection to end.
+  pub async fn acquire(datastore: Arc<dyn Datastore + Send + Sync>) -> Result<Self, String> {
+    let name = format!("{LEASES_PREFIX}{}", token());
+    datastore.put_object(&name, now().to_string().as_bytes())?;
+    let name = Arc::new(Mutex::new(Some(name)));
+    let refresher = tokio::spawn(refresh_lease(datastore.clone(), name.clone()));
+    let lease = Self {
+      datastore,
+      name,
+      refresher,
+    };
+
+    // The lease is written first, so that a collection starting meanwhile sees it
+    if is_fresh(lease.datastore.as_ref(), GC_LOCK) {
+      Logger::info("Waiting for the garbage collection of the datastore to end");
+      while is_fresh(lease.datastore.as_ref(), GC_LOCK) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+      }
+    }
+    Ok(lease)
+  }
+}
+
+impl Drop for WriteLease {
+  fn drop(&mut self) {
+    self.refresher.abort();
+    let name = self.name.lock().ok().and_then(|mut name| name.take());
+    if let Some(name) = name {
+      let _ = self.datastore.delete_object(&name);
+    }
+  }
+}
+
+async fn refresh_lease(
+  datastore: Arc<dyn Datastore + Send + Sync>,
+  name: Arc<Mutex<Option<String>>>,
+) {
+  let mut interval = tokio::time::interval(Duration::from_secs(REFRESH_AFTER as u64));
+  interval.tick().await;
+  loop {
+    interval.tick().await;
+    let Ok(name) = name.lock() else {
+      return;
+    };
+    let Some(name) = name.as_ref() else {
+      return;
+    };
+    if let Err(err) = datastore.replace_object(name, now().to_string().as_bytes()) {
+      Logger::warn(&format!("Cannot refresh the lease {}: {}", name, err));
+    }
+  }
+}
+
+/// Held while collecting garbage, only once no object is being stored. Released when dropped.
+pub struct GcLock<'a> {
+  datastore: &'a dyn Datastore,
+  token: String,
+  refreshed: i64,
+}
+
+impl<'a> GcLock<'a> {
+  /// Takes the lock, failing when another collection holds it or objects are being stored. A
+  /// stale lock is replaced, then read back so that only one of the collections replacing it at
+  /// once goes on.
+  pub fn acquire(datastore: &'a dyn Datastore) -> Result<Self, String> {
+    let running = || "Garbage collection of the datastore is already running".to_string();
+    let token = token();
+    let content = format!("{} {token}", now());
+
+    match datastore.get_object(GC_LOCK.to_string()) {
+      Ok(stale) if !is_fresh(datastore, GC_LOCK) => {
+        // Another collection may have replaced it since it was read
+        if datastore.get_object(GC_LOCK.to_string()).ok() != Some(stale) {
+          return Err(running());
+        }
+        datastore.replace_object(GC_LOCK, content.as_bytes())?;
+      }
+      Ok(_) => return Err(running()),
+      Err(_) => datastore
+        .put_object(GC_LOCK, content.as_bytes())
+        .map_err(|_| running())?,
+    }
+    let lock = Self {
+      datastore,
+      token,
+      refreshed: now(),
+    };
+    if !lock.is_owned() {
+      return Err(running());
+    }
+
+    let leases = datastore
+      .list_objects_with_prefix(LEASES_PREFIX)?
+      .into_iter()
+      .filter(|lease| is_fresh(datastore, lease))
+      .count();
+    if leases > 0 {
+      return Err(format!(
+        "Cannot collect garbage while {leases} backups are being stored"
+      ));
+    }
+    Ok(lock)
+  }
+
+  /// Checks that the lock was not taken over, and keeps it from going stale. To be called before
+  /// every deletion.
+  pub fn refresh(&mut self) -> Result<(), String> {
+    if !self.is_owned() {
+      return Err("The garbage collection lock was taken over by another collection".to_string());
+    }
+    let now = now();
+    if now - self.refreshed >= REFRESH_AFTER {
+      let content = format!("{now} {}", self.token);
+      self.datastore.replace_object(GC_LOCK, content.as_bytes())?;
+      self.refreshed = now;
+    }
+    Ok(())
+  }
+
+  fn is_owned(&self) -> bool {
+    self
+      .datastore
+      .get_object(GC_LOCK.to_string())
+      .ok()
+      .and_then(|content| String::from_utf8(content).ok())
+      .is_some_and(|content| content.split_whitespace().nth(1) == Some(self.token.as_str()))
+  }
+}
+
+impl Drop for GcLock<'_> {
+  fn drop(&mut self) {
+    if self.is_owned() {
+      let _ = self.datastore.delete_object(GC_LOCK);
+    }
+  }
+}
+
+fn now() -> i64 {
+  chrono::Local::now().timestamp()
+}
+
+fn token() -> String {
+  format!(
+    "{}_{}",
+    process::id(),
+    TOKEN_COUNTER.fetch_add(1, Ordering::Relaxed)
+  )
+}
+
+/// Tells whether a lease or lock exists and was refreshed recently.
+fn is_fresh(datastore: &dyn Datastore, object_name: &str) -> bool {
+  datastore
+    .get_object(object_name.to_string())
+    .ok()
+    .and_then(|content| {
+      String::from_utf8(content)
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse::<i64>()
+        .ok()
+    })
+    .is_some_and(|refreshed| now() - refreshed < STALE_AFTER)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::Arc;
+
+  use crate::{
+    datastores::{
+      Datastore, FilesystemDatastore,
+      lock::{GC_LOCK, GcLock, WriteLease},
+    },
+    tests::{clean_test_dir, get_test_dir_path},
+  };
+
+  #[tokio::test]
+  async fn lock_gc_and_leases() {
+    let test_dir_path = get_test_dir_path("lock_gc_and_leases");
+    clean_test_dir(test_dir_path.clone());
+    let datastore = Arc::new(FilesystemDatastore::new(test_dir_path.as_str()));
+
+    // Objects being stored keep garbage from being collected
+    let lease = WriteLease::acquire(datastore.clone()).await.unwrap();
+    let other_lease = WriteLease::acquire(datastore.clone()).await.unwrap();
+    assert!(GcLock::acquire(datastore.as_ref()).is_err());
+    drop(lease);
+    assert!(GcLock::acquire(datastore.as_ref()).is_err());
+    drop(other_lease);
+
+    let mut lock = GcLock::acquire(datastore.as_ref()).unwrap();
+    assert!(GcLock::acquire(datastore.as_ref()).is_err());
+    lock.refresh().unwrap();
+    drop(lock);
+    assert!(datastore.list_objects().unwrap().is_empty());
+
+    // Locks left by a crashed process are taken over
+    datastore.put_object(GC_LOCK, b"0 1_1").unwrap();
+    drop(WriteLease::acquire(datastore.clone()).await.unwrap());
+    let mut lock = GcLock::acquire(datastore.as_ref()).unwrap();
+    lock.refresh().unwrap();
+
+    // A collection whose lock was taken over stops, leaving the lock to its new owner
+    datastore.replace_object(GC_LOCK, b"0 1_2").unwrap();
+    assert!(lock.refresh().is_err());
+    drop(lock);
+    assert!(datastore.object_exists(GC_LOCK));
+    drop(GcLock::acquire(datastore.as_ref()).unwrap());
+
+    datastore.put_object("locks/lease_1_1", b"0").unwrap();
+    drop(GcLock::acquire(datastore.as_ref()).unwrap());
+
+    clean_test_dir(test_dir_path);
+  }
+}
@@ -1,13 +1,60 @@
-use std::io::Bytes;
-
+pub mod dedup;
 pub mod filesystem;
+pub mod lock;
+pub use dedup::DedupStore;
 pub use filesystem::FilesystemDatastore;
+pub use lock::{GcLock, WriteLease};
+
+use std::sync::Arc;
+
+use crate::utils::config::{BackupDatastore, BackupDatastoreType};
 
 pub trait Datastore {
-  fn new(base_path: &str) -> Self;
+  fn new(base_path: &str) -> Self
+  where
+    Self: Sized;
 
-  fn get_object(&self, path: String) -> Result<String, String>;
+  fn get_object(&self, path: String) -> Result<Vec<u8>, String>;
   fn list_objects(&self) -> Result<Vec<String>, String>;
+  /// Lists the objects whose name starts with `prefix`, like `cool/2026/` for the objects stored
+  /// under that directory.
+  fn list_objects_with_prefix(&self, prefix: &str) -> Result<Vec<String>, String> {
+    let mut objects = self.list_objects()?;
+    objects.retain(|object| object.starts_with(prefix));
+    Ok(objects)
+  }
+  fn object_exists(&self, object_name: &str) -> bool;
   fn put_object(&self, object_name: &str, object_content: &[u8]) -> Result<(), String>;
+  /// Writes an object, replacing the existing one if any. Datastores able to replace objects
+  /// atomically override this.
+  fn replace_object(&self, object_name: &str, object_content: &[u8]) -> Result<(), String> {
+    if self.object_exists(object_name) {
+      self.delete_object(object_name)?;
+    }
+    self.put_object(object_name, object_content)
+  }
   fn delete_object(&self, object_name: &str) -> Result<(), String>;
 }
+
+/// Stores an object named after its content, like a chunk, and tells whether it was written. The
+/// object existing already, maybe written meanwhile by a concurrent backup, holds the same content.
+pub fn put_shared_object(
+  datastore: &dyn Datastore,
+  object_name: &str,
+  content: &[u8],
+) -> Result<bool, String> {
+  match datastore.put_object(object_name, content) {
+    Ok(()) => Ok(true),
+    Err(_) if datastore.object_exists(object_name) => Ok(false),
+    Err(err) => Err(err),
+  }
+}
+
+pub fn from_config(
+  datastore: &BackupDatastore,
+) -> Result<Arc<dyn Datastore + Send + Sync>, String> {
+  match datastore.storage_type {
+    BackupDatastoreType::FileSystem => Ok(Arc::new(FilesystemDatastore::new(&datastore.path))),
+    BackupDatastoreType::S3 => Err("S3 datastores are not supported yet".to_string()),
+  }
+}
@@ -1,19 +1,67 @@
+use std::sync::Arc;
+
+use bson::{Bson, Document, RawDocumentBuf, Timestamp, doc};
 use mongodb::{
-  Client,
-  error::{Error, Result},
+  Client, ClientSession, Database,
+  change_stream::{ChangeStream, event::ResumeToken},
+  error::{CommandError, Error, ErrorKind, Result},
+  options::{
+    ClientOptions, FindOptions, FullDocumentType, ReadPreference, ReadPreferenceOptions,
+    SelectionCriteria,
+  },
+};
+
+use crate::{
+  db::Throttle,
+  utils::config::{BackupReadPreference, ReadPreferenceMode},
 };
 
+/// Error code of commands the connected user is not allowed to run.
+pub const UNAUTHORIZED: i32 = 13;
+/// Error code of `create` when the collection already exists.
+pub const NAMESPACE_EXISTS: i32 = 48;
+
+/// Number of documents read between two calls to the throttle.
+const THROTTLE_BATCH_SIZE: u64 = 100;
+/// Reads are handed over in batches of at most this many documents or bytes, so that the memory
+/// they hold does not grow with the size of the collection.
+pub const BATCH_DOCUMENTS: usize = 10_000;
+pub const BATCH_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Clone)]
 pub struct DatabaseConnection {
   client: Option<Client>,
+  read_preference: Option<ReadPreference>,
+  /// Shared by the clones of the connection, so that their reads are paced together.
+  throttle: Option<Arc<Throttle>>,
 }
 
 impl DatabaseConnection {
   pub fn new() -> Self {
-    Self { client: None }
+    Self {
+      client: None,
+      read_preference: None,
+      throttle: None,
+    }
+  }
+
+  /// Sets the members reads are sent to, overriding the connection string. Only applies to the
+  /// next `connect`.
+  pub fn set_read_preference(&mut self, read_preference: ReadPreference) {
+    self.read_preference = Some(read_preference);
+  }
+
+  /// Paces the reads of `find_batches`.
+  pub fn set_throttle(&mut self, throttle: Throttle) {
+    self.throttle = Some(Arc::new(throttle));
   }
 
   pub async fn connect(&mut self, uri: &str) -> Result<()> {
-    let client = Client::with_uri_str(uri).await?;
+    let mut options = ClientOptions::parse(uri).await?;
+    if let Some(read_preference) = &self.read_preference {
+      options.selection_criteria = Some(SelectionCriteria::ReadPreference(read_preference.clone()));
+    }
+    let client = Client::with_options(options)?;
     self.client = Some(client);
     Ok(())
   }
@@ -34,6 +82,13 @@ impl DatabaseConnection {
     self.client.as_ref()
   }
 
+  fn connected_client(&self) -> Result<&Client> {
+    self
+      .client
+      .as_ref()
+      .ok_or(Error::custom("No connected databases"))
+  }
+
   pub async fn list_databases(&self) -> Result<Vec<String>> {
     if let Some(c) = &self.client {
       Ok(c.list_database_names().await?)
@@ -41,4 +96,530 @@ impl DatabaseConnection {
       Err(Error::custom("No connected databases"))
     }
   }
+
+  /// Returns the database named in the connection string, if any.
+  pub fn default_database(&self) -> Result<Option<Database>> {
+    Ok(self.connected_client()?.default_database())
+  }
+
+  pub fn database(&self, name: &str) -> Result<Database> {
+    Ok(self.connected_client()?.database(name))
+  }
+
+  /// Lists the regular and time-series collections of a database, leaving out views and
+  /// `system.*` collections.
+  pub async fn list_collections(&self, database: &str) -> Result<Vec<String>> {
+    let mut names = self
+      .database(database)?
+      .list_collection_names()
+      .filter(doc! { "type": { "$in": ["collection", "timeseries"] } })
+      .await?;
+    names.retain(|name| !name.starts_with("system."));
+    names.sort();
+    Ok(names)
+  }
+
+  /// Lists the specifications of the views of a database, their definition being in `options`.
+  pub async fn list_views(&self, database: &str) -> Result<Vec<Document>> {
+    let mut cursor = self
+      .database(database)?
+      .run_cursor_command(doc! { "listCollections": 1, "filter": { "type": "view" } })
+      .await?;
+
+    let mut views = Vec::new();
+    while cursor.advance().await? {
+      views.push(cursor.deserialize_current()?);
+    }
+    Ok(views)
+  }
+
+  /// Lists the users defined on a database, with their credentials.
+  pub async fn users_info(&self, database: &str) -> Result<Vec<Document>> {
+    let reply = self
+      .database(database)?
+      .run_command(doc! { "usersInfo": 1, "showCredentials": true })
+      .await?;
+    Ok(documents(&reply, "users"))
+  }
+
+  /// Lists the roles defined on a database, with their privileges.
+  pub async fn roles_info(&self, database: &str) -> Result<Vec<Document>> {
+    let reply = self
+      .database(database)?
+      .run_command(doc! { "rolesInfo": 1, "showPrivileges": true })
+      .await?;
+    Ok(documents(&reply, "roles"))
+  }
+
+  /// Tells whether the connection goes through a `mongos` router of a sharded cluster.
+  pub async fn is_mongos(&self) -> Result<bool> {
+    let hello = self
+      .database("admin")?
+      .run_command(doc! { "hello": 1 })
+      .await?;
+
+    Ok(hello.get_str("msg").ok() == Some("isdbgrid"))
+  }
+
+  /// Lists the `config.collections` entries of the sharded collections of databases.
+  pub async fn sharded_collections(&self, databases: &[String]) -> Result<Vec<Document>> {
+    let databases: Vec<String> = databases.iter().map(|d| regex::escape(d)).collect();
+    let filter = doc! {
+      "_id": { "$regex": format!("^({})\\.", databases.join("|")) },
+      "dropped": { "$ne": true },
+    };
+
+    let mut cursor = self
+      .database("config")?
+      .collection::<Document>("collections")
+      .find(filter)
+      .await?;
+
+    let mut collections = Vec::new();
+    while cursor.advance().await? {
+      collections.push(cursor.deserialize_current()?);
+    }
+    Ok(collections)
+  }
+
+  /// Starts or stops the balancer of a sharded cluster.
+  pub async fn set_balancer(&self, enabled: bool) -> Result<()> {
+    let command = if enabled {
+      doc! { "balancerStart": 1 }
+    } else {
+      doc! { "balancerStop": 1 }
+    };
+    self.database("admin")?.run_command(command).await?;
+    Ok(())
+  }
+
+  /// Enables sharding for a database, which servers before 6.0 require to shard its collections.
+  pub async fn enable_sharding(&self, database: &str) -> Result<()> {
+    self
+      .database("admin")?
+      .run_command(doc! { "enableSharding": database })
+      .await?;
+    Ok(())
+  }
+
+  /// Keeps the balancer from moving the chunks of a collection, like `sh.disableBalancing`.
+  pub async fn disable_balancing(&self, namespace: &str) -> Result<()> {
+    self
+      .database("config")?
+      .collection::<Document>("collections")
+      .update_one(
+        doc! { "_id": namespace },
+        doc! { "$set": { "noBalance": true } },
+      )
+      .await?;
+    Ok(())
+  }
+
+  /// Shards a collection with the given key, creating it when it does not exist.
+  pub async fn shard_collection(
+    &self,
+    namespace: &str,
+    key: Document,
+    unique: bool,
+    simple_collation: bool,
+  ) -> Result<()> {
+    let mut command = doc! { "shardCollection": namespace, "key": key, "unique": unique };
+    // Collections with a default collation are sharded on the simple collation
+    if simple_collation {
+      command.insert("collation", doc! { "locale": "simple" });
+    }
+    self.database("admin")?.run_command(command).await?;
+    Ok(())
+  }
+
+  /// Tells whether the server is a replica set member or a `mongos`, which both support snapshot
+  /// reads.
+  pub async fn supports_snapshots(&self) -> Result<bool> {
+    let hello = self
+      .database("admin")?
+      .run_command(doc! { "hello": 1 })
+      .await?;
+
+    Ok(hello.contains_key("setName") || hello.get_str("msg").ok() == Some("isdbgrid"))
+  }
+
+  /// Starts a session whose reads all see the data as it was at the time of the first one.
+  pub async fn start_snapshot_session(&self) -> Result<ClientSession> {
+    self
+      .connected_client()?
+      .start_session()
+      .snapshot(true)
+      .await
+  }
+
+  /// Starts a session whose reads all see the data as it was at `time`, sharing the snapshot of
+  /// another session.
+  pub async fn start_snapshot_session_at(&self, time: Timestamp) -> Result<ClientSession> {
+    self
+      .connected_client()?
+      .start_session()
+      .snapshot(true)
+      .snapshot_time(time)
+      .await
+  }
+
+  /// Makes the server pick the time of a snapshot session by reading one document of a
+  /// collection, and returns it.
+  pub async fn pin_snapshot(
+    &self,
+    session: &mut ClientSession,
+    database: &str,
+    collection: &str,
+  ) -> Result<Option<Timestamp>> {
+    self
+      .database(database)?
+      .collection::<RawDocumentBuf>(collection)
+      .find_one(doc! {})
+      .session(&mut *session)
+      .await?;
+    Ok(session.snapshot_time())
+  }
+
+  /// Returns the estimated number of documents of a collection, read from its metadata.
+  pub async fn estimated_count(&self, database: &str, collection: &str) -> Result<u64> {
+    self
+      .database(database)?
+      .collection::<Document>(collection)
+      .estimated_document_count()
+      .await
+  }
+
+  /// Returns the `_id` of up to `size` random documents matching `filter`, sorted.
+  pub async fn sample_ids(
+    &self,
+    database: &str,
+    collection: &str,
+    filter: Document,
+    size: usize,
+  ) -> Result<Vec<Bson>> {
+    // `$sample` picks random documents without scanning the collection only as the first stage
+    let mut pipeline = Vec::new();
+    if !filter.is_empty() {
+      pipeline.push(doc! { "$match": filter });
+    }
+    pipeline.extend([
+      doc! { "$sample": { "size": size as i64 } },
+      doc! { "$project": { "_id": 1 } },
+      doc! { "$sort": { "_id": 1 } },
+    ]);
+
+    let mut cursor = self
+      .database(database)?
+      .collection::<Document>(collection)
+      .aggregate(pipeline)
+      .await?;
+
+    let mut ids = Vec::new();
+    while cursor.advance().await? {
+      if let Some(id) = cursor.deserialize_current()?.remove("_id") {
+        ids.push(id);
+      }
+    }
+    Ok(ids)
+  }
+
+  /// Reads every document of a collection matching `filter`, projected and sorted by `options`,
+  /// keeping them as raw BSON, and hands them over to `on_batch` in batches of at most
+  /// `BATCH_DOCUMENTS` documents or `BATCH_BYTES` bytes, the last one, possibly empty, being
+  /// flagged. Reading stops when `on_batch` returns `false`. Reads happen in `session` when given.
+  pub async fn find_batches<F: Future<Output = bool>>(
+    &self,
+    database: &str,
+    collection: &str,
+    filter: Document,
+    options: FindOptions,
+    session: Option<&mut ClientSession>,
+    mut on_batch: impl FnMut(Vec<RawDocumentBuf>, bool) -> F,
+  ) -> Result<()> {
+    let collection = self
+      .database(database)?
+      .collection::<RawDocumentBuf>(collection);
+    let mut batch = DocumentBatch::default();
+    let mut pending = (0, 0);
+    match session {
+      Some(session) => {
+        let mut cursor = collection
+          .find(filter)
+          .with_options(options)
+          .session(&mut *session)
+          .await?;
+        while cursor.advance(session).await? {
+          self
+            .throttle(&mut pending, cursor.current().as_bytes().len())
+            .await;
+          if let Some(documents) = batch.push(cursor.current().to_owned())
+            && !on_batch(documents, false).await
+          {
+            return Ok(());
+          }
+        }
+      }
+      None => {
+        let mut cursor = collection.find(filter).with_options(options).await?;
+        while cursor.advance().await? {
+          self
+            .throttle(&mut pending, cursor.current().as_bytes().len())
+            .await;
+          if let Some(documents) = batch.push(cursor.current().to_owned())
+            && !on_batch(documents, false).await
+          {
+            return Ok(());
+          }
+        }
+      }
+    }
+    self.throttle_remainder(pending).await;
+    on_batch(batch.documents, true).await;
+    Ok(())
+  }
+
+  /// Accounts for a read document, waiting on the throttle once every batch.
+  async fn throttle(&self, pending: &mut (u64, u64), bytes: usize) {
+    let Some(throttle) = &self.throttle else {
+      return;
+    };
+    *pending = (pending.0 + 1, pending.1 + bytes as u64);
+    if pending.0 >= THROTTLE_BATCH_SIZE {
+      throttle.wait(self, pending.0, pending.1).await;
+      *pending = (0, 0);
+    }
+  }
+
+  /// Accounts for the documents read since the last wait once a cursor is exhausted, so that
+  /// reads of small collections count too.
+  async fn throttle_remainder(&self, pending: (u64, u64)) {
+    if let Some(throttle) = &self.throttle
+      && pending.0 > 0
+    {
+      throttle.wait(self, pending.0, pending.1).await;
+    }
+  }
+
+  pub async fn replica_set_status(&self) -> Result<Document> {
+    self
+      .database("admin")?
+      .run_command(doc! { "replSetGetStatus": 1 })
+      .await
+  }
+
+  /// Returns the timestamp of the first or last entry of the oplog, or `None` when the server is
+  /// not part of a replica set.
+  pub async fn oplog_timestamp(&self, latest: bool) -> Result<Option<Timestamp>> {
+    let entry = self
+      .database("local")?
+      .collection::<Document>("oplog.rs")
+      .find_one(doc! {})
+      .sort(doc! { "$natural": if latest { -1 } else { 1 } })
+      .projection(doc! { "ts": 1 })
+      .await?;
+
+    Ok(entry.and_then(|entry| entry.get_timestamp("ts").ok()))
+  }
+
+  /// Reads the oplog entries touching `databases` after `start` and up to `end`, in oplog order.
+  pub async fn read_oplog(
+    &self,
+    databases: &[String],
+    start: Timestamp,
+    end: Timestamp,
+  ) -> Result<Vec<RawDocumentBuf>> {
+    let mut cursor = self
+      .database("local")?
+      .collection::<RawDocumentBuf>("oplog.rs")
+      .find(oplog_filter(databases, start, end))
+      .sort(doc! { "$natural": 1 })
+      .await?;
+
+    let mut entries = Vec::new();
+    while cursor.advance().await? {
+      entries.push(cursor.current().to_owned());
+    }
+    Ok(entries)
+  }
+
+  /// Opens a change stream on databases, resuming after `resume_token` when given. Several
+  /// databases are watched through a cluster-wide change stream. Update events carry the full
+  /// document as it was after the update.
+  pub async fn watch(
+    &self,
+    databases: &[String],
+    resume_token: Option<ResumeToken>,
+  ) -> Result<ChangeStream<RawDocumentBuf>> {
+    let stream = match databases {
+      [database] => {
+        self
+          .database(database)?
+          .watch()
+          .full_document(FullDocumentType::UpdateLookup)
+          .resume_after(resume_token)
+          .await?
+      }
+      _ => {
+        self
+          .connected_client()?
+          .watch()
+          .pipeline([doc! { "$match": { "ns.db": { "$in": databases } } }])
+          .full_document(FullDocumentType::UpdateLookup)
+          .resume_after(resume_token)
+          .await?
+      }
+    };
+
+    Ok(stream.with_type::<RawDocumentBuf>())
+  }
+
+  /// Lists the index specifications of a collection, with all their options.
+  pub async fn list_indexes(&self, database: &str, collection: &str) -> Result<Vec<Document>> {
+    let mut cursor = self
+      .database(database)?
+      .run_cursor_command(doc! { "listIndexes": collection })
+      .await?;
+
+    let mut indexes = Vec::new();
+    while cursor.advance().await? {
+      indexes.push(cursor.deserialize_current()?);
+    }
+    Ok(indexes)
+  }
+
+  /// Returns the options a collection was created with, like `capped` or `validator`.
+  pub async fn collection_options(&self, database: &str, collection: &str) -> Result<Document> {
+    let mut cursor = self
+      .database(database)?
+      .run_cursor_command(doc! { "listCollections": 1, "filter": { "name": collection } })
+      .await?;
+
+    if !cursor.advance().await? {
+      return Ok(Document::new());
+    }
+    let specification: Document = cursor.deserialize_current()?;
+    Ok(
+      specification
+        .get_document("options")
+        .cloned()
+        .unwrap_or_default(),
+    )
+  }
+}
+
+/// Returns the code of a command error, like [`UNAUTHORIZED`].
+pub fn command_error_code(err: &Error) -> Option<i32> {
+  match *err.kind {
+    ErrorKind::Command(CommandError { code, .. }) => Some(code),
+    _ => None,
+  }
+}
+
+fn documents(reply: &Document, field: &str) -> Vec<Document> {
+  reply
+    .get_array(field)
+    .map(|values| {
+      values
+        .iter()
+        .filter_map(|value| value.as_document().cloned())
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Turns the read preference of a backup into the one of the driver.
+pub fn read_preference(preference: &BackupReadPreference) -> ReadPreference {
+  let options = (!preference.tags.is_empty()).then(|| {
+    ReadPreferenceOptions::builder()
+      .tag_sets(preference.tags.clone())
+      .build()
+  });
+
+  match preference.mode {
+    ReadPreferenceMode::Primary => ReadPreference::Primary,
+    ReadPreferenceMode::PrimaryPreferred => ReadPreference::PrimaryPreferred { options },
+    ReadPreferenceMode::Secondary => ReadPreference::Secondary { options },
+    ReadPreferenceMode::SecondaryPreferred => ReadPreference::SecondaryPreferred { options },
+    ReadPreferenceMode::Nearest => ReadPreference::Nearest { options },
+  }
+}
+
+/// Matches the oplog entries of databases, including the transactions applying operations on them.
+pub fn oplog_filter(databases: &[String], start: Timestamp, end: Timestamp) -> Document {
+  let databases: Vec<String> = databases.iter().map(|d| regex::escape(d)).collect();
+  let namespace = format!("^({})\\.", databases.join("|"));
+
+  doc! {
+    "ts": { "$gt": start, "$lte": end },
+    "$or": [
+      { "ns": { "$regex": &namespace } },
+      { "o.applyOps.ns": { "$regex": &namespace } },
+    ],
+  }
+}
+
+/// Documents read since the last batch handed over.
+#[derive(Default)]
+struct DocumentBatch {
+  documents: Vec<RawDocumentBuf>,
+  bytes: usize,
+}
+
+impl DocumentBatch {
+  /// Adds a document, returning the batch once full.
+  fn push(&mut self, document: RawDocumentBuf) -> Option<Vec<RawDocumentBuf>> {
+    self.bytes += document.as_bytes().len();
+    self.documents.push(document);
+    if self.documents.len() < BATCH_DOCUMENTS && self.bytes < BATCH_BYTES {
+      return None;
+    }
+    self.bytes = 0;
+    Some(std::mem::take(&mut self.documents))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use bson::{Timestamp, doc, rawdoc};
+
+  use crate::db::connection::{BATCH_BYTES, BATCH_DOCUMENTS, DocumentBatch, oplog_filter};
+
+  #[test]
+  fn connection_oplog_filter() {
+    let start = Timestamp {
+      time: 10,
+      increment: 1,
+    };
+    let end = Timestamp {
+      time: 20,
+      increment: 4,
+    };
+
+    assert_eq!(
+      oplog_filter(&["app".to_string(), "db+1".to_string()], start, end),
+      doc! {
+        "ts": { "$gt": start, "$lte": end },
+        "$or": [
+          { "ns": { "$regex": "^(app|db\\+1)\\." } },
+          { "o.applyOps.ns": { "$regex": "^(app|db\\+1)\\." } },
+        ],
+      }
+    );
+  }
+
+  #[test]
+  fn connection_document_batch() {
+    let mut batch = DocumentBatch::default();
+    for id in 1..BATCH_DOCUMENTS {
+      assert_eq!(batch.push(rawdoc! { "_id": id as i64 }), None);
+    }
+    let documents = batch.push(rawdoc! { "_id": 0 }).unwrap();
+    assert_eq!(documents.len(), BATCH_DOCUMENTS);
+    assert!(batch.documents.is_empty());
+
+    // Large documents fill batches sooner
+    let large = rawdoc! { "data": "x".repeat(BATCH_BYTES / 2) };
+    assert_eq!(batch.push(large.clone()), None);
+    assert_eq!(batch.push(large).map(|documents| documents.len()), Some(2));
+  }
 }
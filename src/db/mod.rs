@@ -1,2 +1,4 @@
 pub mod connection;
 pub use connection::DatabaseConnection;
+pub mod throttle;
+pub use throttle::Throttle;
@@ -0,0 +1,278 @@
+use std::time::Duration;
+
+use bson::{DateTime, Document};
+use tokio::{sync::Mutex, time::Instant};
+
+use crate::{db::DatabaseConnection, utils::logger::Logger};
+
+/// `replSetGetStatus` member states.
+const PRIMARY: i32 = 1;
+const SECONDARY: i32 = 2;
+/// Time between two reads of the replication lag.
+const LAG_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Longest pause between two reads of the replication lag while it is too high.
+const MAX_LAG_BACKOFF: Duration = Duration::from_secs(60);
+/// Most reading time left unused while idle which later reads may catch up on at once.
+const MAX_IDLE_CREDIT: Duration = Duration::from_secs(1);
+
+/// Paces the reads of every clone of a connection, keeping them under a documents and bytes rate,
+/// and pausing them while the replication lag is too high.
+pub struct Throttle {
+  documents_per_second: Option<u64>,
+  bytes_per_second: Option<u64>,
+  max_replication_lag: Option<Duration>,
+  state: Mutex<ThrottleState>,
+}
+
+struct ThrottleState {
+  started: Instant,
+  documents: u64,
+  bytes: u64,
+  lag_checked: Option<Instant>,
+  /// Cleared when the server cannot report its replication lag.
+  lag_available: bool,
+}
+
+impl Throttle {
+  pub fn new(
+    documents_per_second: Option<u64>,
+    bytes_per_second: Option<u64>,
+    max_replication_lag: Option<Duration>,
+  ) -> Self {
+    Self {
+      documents_per_second,
+      bytes_per_second,
+      max_replication_lag,
+      state: Mutex::new(ThrottleState {
+        started: Instant::now(),
+        documents: 0,
+        bytes: 0,
+        lag_checked: None,
+        lag_available: true,
+      }),
+    }
+  }
+
+  /// Accounts for read documents, and waits until reading more keeps within the limits.
+  pub async fn wait(&self, connection: &DatabaseConnection, documents: u64, bytes: u64) {
+    let deadline = {
+      let mut state = self.state.lock().await;
+      // Other readers wait for the lock, so they pause along while the lag is too high
+      if let Some(max_lag) = self.max_replication_lag {
+        self.check_lag(connection, &mut state, max_lag).await;
+      }
+
+      state.charge(
+        documents,
+        bytes,
+        self.documents_per_second,
+        self.bytes_per_second,
+        Instant::now(),
+      )
+    };
+    tokio::time::sleep_until(deadline).await;
+  }
+
+  async fn check_lag(
+    &self,
+    connection: &DatabaseConnection,
+    state: &mut ThrottleState,
+    max_lag: Duration,
+  ) {
+    if !state.lag_available
+      || state
+        .lag_checked
+        .is_some_and(|checked| checked.elapsed() < LAG_CHECK_INTERVAL)
+    {
+      return;
+    }
+
+    let mut backoff = Duration::from_secs(1);
+    let paused = Instant::now();
+    loop {
+      let lag = match connection.replica_set_status().await {
+        Ok(status) => replication_lag(&status),
+        Err(err) => {
+          Logger::warn(&format!(
+            "Cannot read the replication lag, reads are not slowed down by it: {}",
+            err
+          ));
+          state.lag_available = false;
+          return;
+        }
+      };
+      state.lag_checked = Some(Instant::now());
+
+      match lag {
+        Some(lag) if lag > max_lag => {
+          Logger::warn(&format!(
+            "Replication lag is {}s, pausing reads for {}s",
+            lag.as_secs(),
+            backoff.as_secs()
+          ));
+          tokio::time::sleep(backoff).await;
+          backoff = (backoff * 2).min(MAX_LAG_BACKOFF);
+        }
+        _ => break,
+      }
+    }
+
+    // Pauses do not count as time the rate limits could have used
+    state.started += paused.elapsed();
+  }
+}
+
+impl ThrottleState {
+  /// Accounts for read documents at `now`, and returns when reading more keeps within the limits.
+  fn charge(
+    &mut self,
+    documents: u64,
+    bytes: u64,
+    documents_per_second: Option<u64>,
+    bytes_per_second: Option<u64>,
+    now: Instant,
+  ) -> Instant {
+    // Time spent idle beyond the reads already paced for is not saved up for a burst of reads
+    let paced = self.started
+      + pacing_delay(
+        self.documents,
+        self.bytes,
+        documents_per_second,
+        bytes_per_second,
+      );
+    let idle = now.saturating_duration_since(paced);
+    if idle > MAX_IDLE_CREDIT {
+      self.started += idle - MAX_IDLE_CREDIT;
+    }
+
+    self.documents += documents;
+    self.bytes += bytes;
+    self.started
+      + pacing_delay(
+        self.documents,
+        self.bytes,
+        documents_per_second,
+        bytes_per_second,
+      )
+  }
+}
+
+/// Time since the start of the reads before which reading `documents` and `bytes` would exceed
+/// the rate limits.
+pub fn pacing_delay(
+  documents: u64,
+  bytes: u64,
+  documents_per_second: Option<u64>,
+  bytes_per_second: Option<u64>,
+) -> Duration {
+  let seconds =
+    |amount: u64, rate: Option<u64>| rate.map_or(0.0, |rate| amount as f64 / rate.max(1) as f64);
+  Duration::from_secs_f64(
+    seconds(documents, documents_per_second).max(seconds(bytes, bytes_per_second)),
+  )
+}
+
+/// Reads from a `replSetGetStatus` reply how far the furthest secondary is behind the primary.
+pub fn replication_lag(status: &Document) -> Option<Duration> {
+  let members: Vec<&Document> = status
+    .get_array("members")
+    .ok()?
+    .iter()
+    .filter_map(|member| member.as_document())
+    .collect();
+  let optime = |state: i32| {
+    members
+      .iter()
+      .filter(move |member| member.get_i32("state").ok() == Some(state))
+      .filter_map(|member| member.get_datetime("optimeDate").ok())
+  };
+
+  let primary: DateTime = *optime(PRIMARY).next()?;
+  optime(SECONDARY)
+    .map(|secondary| {
+      let lag = primary.timestamp_millis() - secondary.timestamp_millis();
+      Duration::from_millis(lag.max(0) as u64)
+    })
+    .max()
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::Duration;
+
+  use bson::{DateTime, doc};
+
+  use tokio::time::Instant;
+
+  use crate::db::throttle::{ThrottleState, pacing_delay, replication_lag};
+
+  #[test]
+  fn throttle_pacing_delay() {
+    assert_eq!(pacing_delay(1000, 0, None, None), Duration::ZERO);
+    assert_eq!(
+      pacing_delay(1000, 0, Some(500), None),
+      Duration::from_secs(2)
+    );
+    assert_eq!(
+      pacing_delay(1000, 4096, Some(1000), Some(1024)),
+      Duration::from_secs(4)
+    );
+    assert_eq!(
+      pacing_delay(250, 0, Some(1000), Some(1024)),
+      Duration::from_millis(250)
+    );
+  }
+
+  #[test]
+  fn throttle_idle_credit() {
+    let started = Instant::now();
+    let mut state = ThrottleState {
+      started,
+      documents: 0,
+      bytes: 0,
+      lag_checked: None,
+      lag_available: true,
+    };
+    let rate = Some(100);
+
+    // Reads starting long after the throttle only catch up on a second of them
+    let at = started + Duration::from_secs(60);
+    assert_eq!(
+      state.charge(500, 0, rate, None, at),
+      at + Duration::from_secs(4)
+    );
+
+    // Reads kept within the limits are paced from the previous ones
+    let next = at + Duration::from_secs(2);
+    assert_eq!(
+      state.charge(100, 0, rate, None, next),
+      at + Duration::from_secs(5)
+    );
+
+    // As are reads after a pause shorter than the credit
+    let resumed = at + Duration::from_millis(5500);
+    assert_eq!(
+      state.charge(100, 0, rate, None, resumed),
+      at + Duration::from_secs(6)
+    );
+  }
+
+  #[test]
+  fn throttle_replication_lag() {
+    let at = DateTime::from_millis;
+    let status = doc! {
+      "set": "rs0",
+      "members": [
+        { "name": "a:27017", "state": 1, "optimeDate": at(100_000) },
+        { "name": "b:27017", "state": 2, "optimeDate": at(97_500) },
+        { "name": "c:27017", "state": 2, "optimeDate": at(99_000) },
+        { "name": "d:27017", "state": 7 },
+      ],
+    };
+    assert_eq!(replication_lag(&status), Some(Duration::from_millis(2500)));
+
+    let no_secondary = doc! { "members": [{ "state": 1, "optimeDate": at(100_000) }] };
+    assert_eq!(replication_lag(&no_secondary), None);
+    assert_eq!(replication_lag(&doc! { "ok": 1 }), None);
+  }
+}
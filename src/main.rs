@@ -4,11 +4,16 @@ use clap::Parser;
 use dotenvy::dotenv;
 
 use crate::{
+  backups::{
+    BackupCatalog, BackupEngine, BackupKind, ChangeStreamRecorder, DumpImporter, RestoreDrill,
+    RestoreEngine, RestoreOptions, Scheduler,
+  },
   cli::{Cli, Commands},
   ui::app::App,
-  utils::config::Config,
+  utils::{config::Config, logger::Logger},
 };
 
+mod backups;
 mod cli;
 mod datastores;
 mod db;
@@ -23,6 +28,92 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
   match cli.command {
     None | Some(Commands::Tui) => App::new().run().await?,
+    Some(Commands::Backup { name, incremental }) => {
+      let engine = BackupEngine::new(&name, config.get_backup(&name)?);
+      if incremental {
+        engine.run_incremental().await?;
+      } else {
+        engine.run().await?;
+      }
+    }
+    Some(Commands::Import {
+      name,
+      path,
+      timestamp,
+    }) => {
+      DumpImporter::new(&name, config.get_backup(&name)?).import(&path, timestamp)?;
+    }
+    Some(Commands::Restore {
+      name,
+      object,
+      uri,
+      database,
+      drop,
+      indexes_first,
+      users_and_roles,
+      to,
+      sanitized,
+    }) => {
+      let backup = config.get_backup(&name)?;
+      let datastore = datastores::from_config(&backup.datastore)?;
+      let key = backup.encryption_key.as_deref();
+      let (object, segments) = match to {
+        Some(to) => {
+          BackupCatalog::load_chain(datastore.as_ref(), &name, object.as_deref(), to, key)?
+        }
+        None => {
+          let object_name = match object {
+            Some(object) => object,
+            None if sanitized => {
+              BackupCatalog::latest_of(datastore.as_ref(), &name, BackupKind::Sanitized)?
+            }
+            None => BackupCatalog::latest(datastore.as_ref(), &name)?,
+          };
+          (
+            BackupCatalog::load(datastore.as_ref(), &object_name, key)?,
+            Vec::new(),
+          )
+        }
+      };
+
+      let options = RestoreOptions {
+        connection_string: uri.unwrap_or(backup.connection_string.clone()),
+        target_database: database,
+        drop,
+        indexes_first,
+        users_and_roles,
+      };
+      RestoreEngine::new(&options)
+        .restore_to(&object, &segments, to)
+        .await?;
+    }
+    Some(Commands::Drill { name }) => {
+      if !RestoreDrill::new(&name, config.get_backup(&name)?)
+        .run()
+        .await?
+      {
+        return Err(format!("Restore drill of {name} failed").into());
+      }
+    }
+    Some(Commands::Stream { name }) => {
+      ChangeStreamRecorder::new(&name, config.get_backup(&name)?)
+        .run()
+        .await?;
+    }
+    Some(Commands::Prune { name, keep }) => {
+      let backup = config.get_backup(&name)?;
+      let keep = keep
+        .or(backup.retention)
+        .ok_or(format!("No retention configured for {name}, set --keep"))?;
+      let datastore = datastores::from_config(&backup.datastore)?;
+      let deleted = BackupCatalog::prune(datastore.as_ref(), &name, keep)?;
+      let (chunks, files) = BackupCatalog::collect_garbage(datastore.as_ref())?;
+      Logger::highlight(&format!(
+        "Pruned {} objects of {name}, {chunks} unreferenced chunks and {files} GridFS objects",
+        deleted.len()
+      ));
+    }
+    Some(Commands::Daemon) => Scheduler::new(&config)?.run().await,
   };
 
   Ok(())
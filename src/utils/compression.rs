@@ -0,0 +1,50 @@
+use std::io::{Read, Write};
+
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+
+pub fn gzip(content: &[u8]) -> Result<Vec<u8>, String> {
+  gzip_with(|encoder| {
+    encoder
+      .write_all(content)
+      .map_err(|err| format!("Cannot compress content: {}", err))
+  })
+}
+
+/// Compresses the content written by `write` as it goes.
+pub fn gzip_with(
+  write: impl FnOnce(&mut dyn Write) -> Result<(), String>,
+) -> Result<Vec<u8>, String> {
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  write(&mut encoder)?;
+
+  encoder
+    .finish()
+    .map_err(|err| format!("Cannot compress content: {}", err))
+}
+
+pub fn gunzip(content: &[u8]) -> Result<Vec<u8>, String> {
+  let mut decompressed = Vec::new();
+  GzDecoder::new(content)
+    .read_to_end(&mut decompressed)
+    .map_err(|err| format!("Cannot decompress content: {}", err))?;
+
+  Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::utils::compression::{gunzip, gzip};
+
+  #[test]
+  fn compression_roundtrip() {
+    let compressed = gzip(b"Compressed backup :)").unwrap();
+
+    assert_eq!(&compressed[..2], &[0x1f, 0x8b]);
+    assert_eq!(gunzip(&compressed).unwrap(), b"Compressed backup :)");
+  }
+
+  #[test]
+  fn compression_invalid_content() {
+    assert!(gunzip(b"not gzip").is_err());
+  }
+}
@@ -0,0 +1,144 @@
+use chrono::{DateTime, Datelike, Local, Timelike};
+
+/// A standard five fields cron expression (minute, hour, day of month, month, day of week).
+#[derive(Debug, PartialEq)]
+pub struct CronSchedule {
+  minutes: Vec<u32>,
+  hours: Vec<u32>,
+  days_of_month: Vec<u32>,
+  months: Vec<u32>,
+  days_of_week: Vec<u32>,
+  restricted_day_of_month: bool,
+  restricted_day_of_week: bool,
+}
+
+impl CronSchedule {
+  pub fn parse(expression: &str) -> Result<Self, String> {
+    let fields: Vec<&str> = expression.split_whitespace().collect();
+    if fields.len() != 5 {
+      return Err(format!(
+        "Invalid cron expression \"{}\": expected 5 fields, found {}",
+        expression,
+        fields.len()
+      ));
+    }
+
+    let mut days_of_week = Self::parse_field(fields[4], 0, 7)?;
+    // Both 0 and 7 stand for sunday
+    if days_of_week.contains(&7) {
+      days_of_week.retain(|d| *d != 7);
+      days_of_week.push(0);
+    }
+
+    Ok(Self {
+      minutes: Self::parse_field(fields[0], 0, 59)?,
+      hours: Self::parse_field(fields[1], 0, 23)?,
+      days_of_month: Self::parse_field(fields[2], 1, 31)?,
+      months: Self::parse_field(fields[3], 1, 12)?,
+      days_of_week,
+      restricted_day_of_month: fields[2] != "*",
+      restricted_day_of_week: fields[4] != "*",
+    })
+  }
+
+  fn parse_field(field: &str, min: u32, max: u32) -> Result<Vec<u32>, String> {
+    let mut values = Vec::new();
+
+    for part in field.split(',') {
+      let (range, step) = match part.split_once('/') {
+        Some((range, step)) => (
+          range,
+          step
+            .parse::<u32>()
+            .ok()
+            .filter(|s| *s > 0)
+            .ok_or(format!("Invalid cron step \"{}\"", step))?,
+        ),
+        None => (part, 1),
+      };
+
+      let (start, end) = if range == "*" {
+        (min, max)
+      } else if let Some((start, end)) = range.split_once('-') {
+        (Self::parse_number(start)?, Self::parse_number(end)?)
+      } else {
+        let value = Self::parse_number(range)?;
+        (value, if part.contains('/') { max } else { value })
+      };
+
+      if start < min || end > max || start > end {
+        return Err(format!(
+          "Cron field \"{}\" is out of range {}-{}",
+          part, min, max
+        ));
+      }
+
+      values.extend((start..=end).step_by(step as usize));
+    }
+
+    values.sort();
+    values.dedup();
+    Ok(values)
+  }
+
+  fn parse_number(value: &str) -> Result<u32, String> {
+    value
+      .parse()
+      .map_err(|_| format!("Invalid cron value \"{}\"", value))
+  }
+
+  pub fn matches(&self, date: &DateTime<Local>) -> bool {
+    let day_of_month = self.days_of_month.contains(&date.day());
+    let day_of_week = self
+      .days_of_week
+      .contains(&date.weekday().num_days_from_sunday());
+
+    // When both day fields are restricted, cron runs when either of them matches
+    let day = match (self.restricted_day_of_month, self.restricted_day_of_week) {
+      (true, true) => day_of_month || day_of_week,
+      _ => day_of_month && day_of_week,
+    };
+
+    day
+      && self.minutes.contains(&date.minute())
+      && self.hours.contains(&date.hour())
+      && self.months.contains(&date.month())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use chrono::{Local, TimeZone};
+
+  use crate::utils::cron::CronSchedule;
+
+  #[test]
+  fn cron_parse_invalid() {
+    assert!(CronSchedule::parse("0 0 * *").is_err());
+    assert!(CronSchedule::parse("60 0 * * *").is_err());
+    assert!(CronSchedule::parse("*/0 0 * * *").is_err());
+    assert!(CronSchedule::parse("a 0 * * *").is_err());
+  }
+
+  #[test]
+  fn cron_matches() {
+    let schedule = CronSchedule::parse("0 */5 * * *").unwrap();
+
+    assert!(schedule.matches(&Local.with_ymd_and_hms(2026, 1, 1, 10, 0, 0).unwrap()));
+    assert!(!schedule.matches(&Local.with_ymd_and_hms(2026, 1, 1, 11, 0, 0).unwrap()));
+    assert!(!schedule.matches(&Local.with_ymd_and_hms(2026, 1, 1, 10, 1, 0).unwrap()));
+  }
+
+  #[test]
+  fn cron_matches_days() {
+    // 2026-01-04 is a sunday
+    let schedule = CronSchedule::parse("30 6 * * 7").unwrap();
+    assert!(schedule.matches(&Local.with_ymd_and_hms(2026, 1, 4, 6, 30, 0).unwrap()));
+    assert!(!schedule.matches(&Local.with_ymd_and_hms(2026, 1, 5, 6, 30, 0).unwrap()));
+
+    let schedule = CronSchedule::parse("0 0 1,15 * 1-5").unwrap();
+    assert!(schedule.matches(&Local.with_ymd_and_hms(2026, 1, 15, 0, 0, 0).unwrap()));
+    assert!(schedule.matches(&Local.with_ymd_and_hms(2026, 1, 5, 0, 0, 0).unwrap()));
+    assert!(!schedule.matches(&Local.with_ymd_and_hms(2026, 1, 4, 0, 0, 0).unwrap()));
+  }
+}
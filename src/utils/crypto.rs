@@ -1,7 +1,12 @@
 use chacha20poly1305::{
-  ChaCha20Poly1305, Key,
-  aead::{KeyInit, OsRng},
+  AeadCore, ChaCha20Poly1305, Key, Nonce,
+  aead::{Aead, KeyInit, OsRng},
 };
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const KEY_SIZE: usize = 32;
+const NONCE_SIZE: usize = 12;
 
 pub fn generate_key() -> String {
   let key = ChaCha20Poly1305::generate_key(&mut OsRng);
@@ -9,11 +14,103 @@ pub fn generate_key() -> String {
   key.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
-pub fn key_to_cipher(s: &str) -> ChaCha20Poly1305 {
+/// Reads a key of `KEY_SIZE` bytes written in hexadecimal, as given by `generate_key`.
+pub fn key_to_cipher(s: &str) -> Result<ChaCha20Poly1305, String> {
+  if s.len() != KEY_SIZE * 2 || !s.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+    return Err(format!(
+      "Encryption keys must be {} hexadecimal characters",
+      KEY_SIZE * 2
+    ));
+  }
   let bytes: Vec<u8> = (0..s.len())
     .step_by(2)
-    .map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("Invalid hex"))
-    .collect();
+    .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+    .collect::<Result<_, _>>()
+    .map_err(|err| format!("Invalid encryption key: {}", err))?;
+
+  Ok(ChaCha20Poly1305::new(Key::from_slice(&bytes)))
+}
+
+/// Encrypts `content` with a random nonce, which is prepended to the ciphertext.
+pub fn encrypt(key: &str, content: &[u8]) -> Result<Vec<u8>, String> {
+  let cipher = key_to_cipher(key)?;
+  let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+  let ciphertext = cipher
+    .encrypt(&nonce, content)
+    .map_err(|err| format!("Cannot encrypt content: {}", err))?;
+
+  Ok([nonce.as_slice(), ciphertext.as_slice()].concat())
+}
+
+pub fn decrypt(key: &str, content: &[u8]) -> Result<Vec<u8>, String> {
+  if content.len() < NONCE_SIZE {
+    return Err("Encrypted content is too short".to_string());
+  }
+
+  let cipher = key_to_cipher(key)?;
+  let (nonce, ciphertext) = content.split_at(NONCE_SIZE);
+
+  cipher
+    .decrypt(Nonce::from_slice(nonce), ciphertext)
+    .map_err(|err| format!("Cannot decrypt content: {}", err))
+}
+
+/// HMAC-SHA256 of `content` in hexadecimal, naming or masking content without revealing it to
+/// whoever does not know `key`.
+pub fn hmac(key: &str, content: &[u8]) -> String {
+  let mut mac =
+    <Hmac<Sha256> as Mac>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any size");
+  mac.update(content);
+
+  mac
+    .finalize()
+    .into_bytes()
+    .iter()
+    .map(|byte| format!("{byte:02x}"))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::utils::crypto::{decrypt, encrypt, generate_key, hmac, key_to_cipher};
+
+  #[test]
+  fn crypto_encrypt_decrypt() {
+    let key = generate_key();
+    let encrypted = encrypt(&key, b"Super secret backup :)").unwrap();
+
+    assert_ne!(encrypted, b"Super secret backup :)");
+    assert_eq!(
+      decrypt(&key, &encrypted).unwrap(),
+      b"Super secret backup :)"
+    );
+  }
+
+  #[test]
+  fn crypto_decrypt_with_wrong_key() {
+    let encrypted = encrypt(&generate_key(), b"Super secret backup :)").unwrap();
+
+    assert!(decrypt(&generate_key(), &encrypted).is_err());
+  }
+
+  #[test]
+  fn crypto_invalid_key() {
+    assert!(key_to_cipher(&generate_key()).is_ok());
+    assert!(key_to_cipher("azertyuiop").is_err());
+    assert!(key_to_cipher(&"zz".repeat(32)).is_err());
+    assert!(key_to_cipher(&"é".repeat(32)).is_err());
+    assert!(encrypt("azertyuiop", b"Super secret backup :)").is_err());
+    assert!(decrypt("azertyuiop", &[0; 64]).is_err());
+  }
 
-  ChaCha20Poly1305::new(Key::from_slice(&bytes))
+  #[test]
+  fn crypto_hmac() {
+    // RFC 4231 test case 2
+    assert_eq!(
+      hmac("Jefe", b"what do ya want for nothing?"),
+      "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+    assert_ne!(hmac("other", b"content"), hmac("secret", b"content"));
+  }
 }
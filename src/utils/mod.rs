@@ -1,3 +1,6 @@
+pub mod compression;
 pub mod config;
+pub mod cron;
 pub mod crypto;
 pub mod logger;
+pub mod pattern;
@@ -0,0 +1,112 @@
+use std::fmt;
+
+use regex::Regex;
+
+/// A name pattern, either a glob or a regular expression written between slashes like
+/// `/^cache\./`. Regular expressions match anywhere in the name unless anchored.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+  Glob(String),
+  Regex(Regex),
+}
+
+impl Pattern {
+  pub fn parse(pattern: &str) -> Result<Self, String> {
+    match pattern
+      .strip_prefix('/')
+      .and_then(|pattern| pattern.strip_suffix('/'))
+    {
+      Some(regex) => Regex::new(regex)
+        .map(Self::Regex)
+        .map_err(|err| format!("Invalid regex pattern {}: {}", pattern, err)),
+      None => Ok(Self::Glob(pattern.to_string())),
+    }
+  }
+
+  pub fn matches(&self, text: &str) -> bool {
+    match self {
+      Self::Glob(pattern) => glob_match(pattern, text),
+      Self::Regex(regex) => regex.is_match(text),
+    }
+  }
+}
+
+impl fmt::Display for Pattern {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Glob(pattern) => write!(f, "{}", pattern),
+      Self::Regex(regex) => write!(f, "/{}/", regex.as_str()),
+    }
+  }
+}
+
+/// Matches `text` against a glob pattern, where `*` matches any sequence of characters and `?`
+/// any single character.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+  let pattern: Vec<char> = pattern.chars().collect();
+  let text: Vec<char> = text.chars().collect();
+
+  let (mut p, mut t) = (0, 0);
+  // Position of the last `*` and of the text it was matched at, to backtrack to
+  let mut star: Option<(usize, usize)> = None;
+
+  while t < text.len() {
+    match pattern.get(p) {
+      Some('*') => {
+        star = Some((p, t));
+        p += 1;
+      }
+      Some(c) if *c == '?' || *c == text[t] => {
+        p += 1;
+        t += 1;
+      }
+      _ => match star {
+        Some((star_p, star_t)) => {
+          star = Some((star_p, star_t + 1));
+          p = star_p + 1;
+          t = star_t + 1;
+        }
+        None => return false,
+      },
+    }
+  }
+
+  pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::utils::pattern::{Pattern, glob_match};
+
+  #[test]
+  fn pattern_glob_match() {
+    assert!(glob_match("app", "app"));
+    assert!(!glob_match("app", "apps"));
+    assert!(glob_match("app_*", "app_prod"));
+    assert!(glob_match("app_*", "app_"));
+    assert!(!glob_match("app_*", "scratch_app"));
+    assert!(glob_match("*_tmp", "users_tmp"));
+    assert!(glob_match("*", ""));
+    assert!(glob_match("a*b*c", "aXXbYYbc"));
+    assert!(!glob_match("a*b*c", "aXXbYY"));
+    assert!(glob_match("db?", "db1"));
+    assert!(!glob_match("db?", "db"));
+  }
+
+  #[test]
+  fn pattern_parse() {
+    let pattern = Pattern::parse("tmp_2024_*").unwrap();
+    assert!(pattern.matches("tmp_2024_01"));
+    assert!(!pattern.matches("tmp_2023_01"));
+    assert_eq!(pattern.to_string(), "tmp_2024_*");
+
+    let pattern = Pattern::parse(r"/^cache\.[a-z]+$/").unwrap();
+    assert!(pattern.matches("cache.users"));
+    assert!(!pattern.matches("cache.users2"));
+    assert!(!pattern.matches("cache"));
+    assert_eq!(pattern.to_string(), r"/^cache\.[a-z]+$/");
+
+    assert!(Pattern::parse("/").unwrap().matches("/"));
+    assert!(Pattern::parse("/(/").is_err());
+  }
+}